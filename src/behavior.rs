@@ -1,77 +1,889 @@
-use std::sync::Arc;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
 
-use once_cell::unsync::OnceCell;
-use reagenz::{BehaviorTree, ScriptSource, BehaviorTreeBuilder, query_fn, CompileError};
+use either::Either;
+use float_ord::FloatOrd;
+use fnv::FnvHashMap;
+use once_cell::sync::OnceCell;
+use reagenz::{
+    BehaviorTree, ScriptSource, BehaviorTreeBuilder, query_fn, cond_fn, effect_fn, CompileError,
+    Outcome,
+};
+use smol_str::SmolStr;
 use treelang::Indent;
 
 use crate::world::World;
-use crate::world::entities::Entity;
+use crate::world::entities::{Entity, EntitySet};
+use crate::world::step::Decisions;
 
+use self::extension::{BehaviorBuilder, BehaviorExtension, CustomEffect, TreeBuilder};
+use self::roots::RootSelector;
+use self::trace::{TraceRecorder, AgentTrace, TraceEvent};
+use self::utility::UtilitySelector;
+
+
+pub mod extension;
+pub mod roots;
+pub mod trace;
+pub mod utility;
 
 pub type Value = reagenz::Value<Entity>;
 pub type Values = reagenz::Values<Entity>;
 
-pub struct Behavior<'a> {
-    tree: BehaviorTree<Context<'a>, Entity, Effect>,
+/// Action agents start evaluating from unless another root is selected.
+pub const ROOT_ACTION: &str = "main";
+
+/// How the decision phase evaluates agents.
+///
+/// All modes produce identical decisions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Evaluation {
+    #[default]
+    Serial,
+    Parallel { threads: NonZeroUsize },
+}
+
+pub struct Behavior {
+    tree: BehaviorTree<Context, Entity, Effect>,
+    indent: Indent,
+    sources: Vec<ScriptSource>,
+    extensions: Vec<Arc<dyn BehaviorExtension>>,
+    roots: RootSelector,
+    utility_selectors: FnvHashMap<SmolStr, UtilitySelector>,
 }
 
-impl<'a> Behavior<'a> {
+impl Behavior {
     pub fn load<I>(indent: Indent, sources: I) -> Result<Self, CompileError>
     where
         I: IntoIterator<Item = ScriptSource>,
     {
-        let mut tree = BehaviorTreeBuilder::default();
-        setup_tree_globals(&mut tree);
-        setup_tree_queries(&mut tree);
-        let tree = tree.compile(indent, sources)?;
-        Ok(Self { tree })
+        Self::builder(indent).sources(sources).build()
+    }
+
+    /// Starts building a behavior that can be extended by the host.
+    pub fn builder(indent: Indent) -> BehaviorBuilder {
+        BehaviorBuilder::new(indent)
+    }
+
+    fn compile(
+        indent: Indent,
+        sources: Vec<ScriptSource>,
+        extensions: Vec<Arc<dyn BehaviorExtension>>,
+    ) -> Result<Self, CompileError> {
+        let tree = compile_tree(indent, sources.iter().cloned(), &extensions)?;
+        Ok(Self {
+            tree,
+            indent,
+            sources,
+            extensions,
+            roots: RootSelector::default(),
+            utility_selectors: FnvHashMap::default(),
+        })
+    }
+
+    pub fn roots(&self) -> &RootSelector {
+        &self.roots
+    }
+
+    pub fn roots_mut(&mut self) -> &mut RootSelector {
+        &mut self.roots
+    }
+
+    /// Use a utility selector whenever `root` is selected as an agent's root action.
+    pub fn set_utility_selector<R>(&mut self, root: R, selector: UtilitySelector)
+    where
+        R: Into<SmolStr>,
+    {
+        self.utility_selectors.insert(root.into(), selector);
+    }
+
+    pub fn utility_selector(&self, root: &str) -> Option<&UtilitySelector> {
+        self.utility_selectors.get(root)
+    }
+
+    /// Recompiles the behavior from its sources.
+    ///
+    /// If compilation fails the previously compiled tree stays in place. Since reloading
+    /// requires exclusive access, a swap can never happen during a decision phase.
+    pub fn reload(&mut self) -> Result<(), CompileError> {
+        self.tree = compile_tree(self.indent, self.sources.iter().cloned(), &self.extensions)?;
+        Ok(())
+    }
+
+    /// Runs the decision phase for all agents against the current state of the world.
+    pub fn decide(&self, world: &World) -> Decisions {
+        self.decide_with(world, Evaluation::Serial)
+    }
+
+    pub fn decide_with(&self, world: &World, evaluation: Evaluation) -> Decisions {
+        self.evaluate_agents(world, evaluation, false)
+            .into_iter()
+            .map(|evaluated| (evaluated.agent, evaluated.effects))
+            .collect()
+    }
+
+    /// Evaluates a single agent against the current state of the world.
+    pub fn decide_agent(&self, world: &World, agent: Entity) -> Vec<Effect> {
+        assert!(world.is_agent(agent));
+        self.evaluate(world, agent, false).effects
+    }
+
+    /// Runs the decision phase while recording a trace of each agent's evaluation.
+    pub fn decide_traced(
+        &self,
+        world: &World,
+        evaluation: Evaluation,
+        recorder: &mut TraceRecorder,
+    ) -> Decisions {
+        let mut traces = Vec::new();
+        let mut decisions = Decisions::default();
+        for evaluated in self.evaluate_agents(world, evaluation, true) {
+            traces.extend(evaluated.trace);
+            decisions.insert(evaluated.agent, evaluated.effects);
+        }
+        traces.sort_by_key(|trace| trace.agent);
+        recorder.push_tick(world.tick(), traces);
+        decisions
+    }
+
+    fn evaluate_agents(
+        &self,
+        world: &World,
+        evaluation: Evaluation,
+        traced: bool,
+    ) -> Vec<Evaluated> {
+        match evaluation {
+            Evaluation::Serial => {
+                world.agents().map(|agent| self.evaluate(world, agent, traced)).collect()
+            },
+            Evaluation::Parallel { threads } => {
+                let agents = world.agents().collect::<Vec<_>>();
+                let chunk_size = agents.len().div_ceil(threads.get()).max(1);
+                std::thread::scope(|scope| {
+                    let handles = agents.chunks(chunk_size).map(|chunk| scope.spawn(move || {
+                        chunk.iter()
+                            .map(|&agent| self.evaluate(world, agent, traced))
+                            .collect::<Vec<_>>()
+                    })).collect::<Vec<_>>();
+                    handles.into_iter()
+                        .flat_map(|handle| handle.join().expect("agent evaluation panicked"))
+                        .collect()
+                })
+            },
+        }
     }
+
+    fn evaluate(&self, world: &World, agent: Entity, traced: bool) -> Evaluated {
+        let ctx = Context::new(world, agent, traced);
+        let busy = world.agent_activity(agent).is_some();
+        let root = if busy {
+            let Some(root) = self.roots.interrupt_root() else {
+                return Evaluated { agent, effects: Vec::new(), trace: None };
+            };
+            root
+        } else {
+            self.roots.select(world, agent)
+        };
+        let mut effects = match self.utility_selectors.get(root) {
+            Some(selector) => selector.evaluate(&self.tree, &ctx, root),
+            None => evaluate_action(&self.tree, &ctx, root),
+        };
+        if busy {
+            if let Some(effects) = &mut effects {
+                effects.insert(0, Effect::InterruptActivity);
+            }
+        }
+        let trace = ctx.trace_events().map(|events| AgentTrace {
+            agent,
+            location: ctx.location(),
+            space: ctx.space(),
+            root: root.clone(),
+            events,
+            effects: effects.clone(),
+        });
+        Evaluated {
+            agent,
+            effects: effects.unwrap_or_default(),
+            trace,
+        }
+    }
+}
+
+fn evaluate_action(
+    tree: &BehaviorTree<Context, Entity, Effect>,
+    ctx: &Context,
+    action: &str,
+) -> Option<Vec<Effect>> {
+    let effects = match tree.evaluate(ctx, action, &[]) {
+        Outcome::Success { effects } => Some(effects),
+        Outcome::Failure => None,
+    };
+    ctx.record(|| TraceEvent::Action { name: action.into(), succeeded: effects.is_some() });
+    effects
+}
+
+struct Evaluated {
+    agent: Entity,
+    effects: Vec<Effect>,
+    trace: Option<AgentTrace>,
+}
+
+fn compile_tree<I>(
+    indent: Indent,
+    sources: I,
+    extensions: &[Arc<dyn BehaviorExtension>],
+) -> Result<BehaviorTree<Context, Entity, Effect>, CompileError>
+where
+    I: IntoIterator<Item = ScriptSource>,
+{
+    let mut tree = BehaviorTreeBuilder::default();
+    setup_tree_globals(&mut tree);
+    setup_tree_queries(&mut tree);
+    setup_tree_conditions(&mut tree);
+    setup_tree_effects(&mut tree);
+    for extension in extensions {
+        extension.setup(&mut tree);
+    }
+    tree.compile(indent, sources)
+}
+
+fn setup_tree_queries(tree: &mut TreeBuilder) {
+    tree.register_query("spaces", query_fn!(ctx => {
+        let spaces = ctx.spaces().filter(|space| ctx.perceives(*space));
+        ctx.traced_query("spaces", &[], spaces.map(Value::Ext))
+    }));
+    tree.register_query("areas", query_fn!(ctx, space: Ext => {
+        ctx.traced_query("areas", &[Value::Ext(space)], ctx.world().is_space(space)
+            .then(|| ctx.world().space_areas(space))
+            .into_iter()
+            .flatten()
+            .filter(|area| ctx.perceives(*area))
+            .map(Value::Ext))
+    }));
+    tree.register_query("child-objects", query_fn!(ctx, parent: Ext => {
+        ctx.traced_query("child-objects", &[Value::Ext(parent)], {
+            ctx.world().child_objects(parent)
+                .filter(|object| ctx.perceives(*object))
+                .map(Value::Ext)
+        })
+    }));
+    tree.register_query("portals", query_fn!(ctx => {
+        let world = ctx.world();
+        let objects = world.space_portal_objects(ctx.space())
+            .filter(|object| ctx.perceives(*object));
+        ctx.traced_query("portals", &[], objects.map(|object| {
+            let target = world.object_portal_target(object).expect("portal target");
+            let target_space = world.object_space(target).expect("portal target space");
+            Value::List(Arc::new([Value::Ext(object), Value::Ext(target_space)]))
+        }))
+    }));
+    tree.register_query("area-agents", query_fn!(ctx => {
+        ctx.traced_query("area-agents", &[], ctx.world().agents_in_area(ctx.location())
+            .filter(|agent| *agent != ctx.agent && ctx.perceives(*agent))
+            .map(Value::Ext))
+    }));
+    tree.register_query("space-agents", query_fn!(ctx => {
+        ctx.traced_query("space-agents", &[], ctx.world().agents_in_space(ctx.space())
+            .filter(|agent| *agent != ctx.agent && ctx.perceives(*agent))
+            .map(Value::Ext))
+    }));
+    tree.register_query("path-to", query_fn!(ctx, target: Ext => {
+        let world = ctx.world();
+        let route = Some(target)
+            .filter(|target| ctx.perceives(*target))
+            .and_then(|target| world.object_area(target))
+            .and_then(|target| world.route(ctx.location(), target))
+            .map(|route| Value::List(route.areas()[1..].iter().copied().map(Value::Ext).collect()));
+        ctx.traced_query("path-to", &[Value::Ext(target)], route)
+    }));
+    tree.register_query("global-attribute", query_fn!(ctx, entity: Ext, attr: Value => {
+        let value = ctx.world().global_attribute_value(entity, &attr)
+            .ok()
+            .flatten()
+            .cloned();
+        ctx.traced_query("global-attribute", &[Value::Ext(entity), attr], value)
+    }));
+    tree.register_query("agent-attribute", query_fn!(ctx, entity: Ext, attr: Value => {
+        let value = ctx.world().agent_attribute_value(ctx.agent, entity, &attr)
+            .ok()
+            .flatten()
+            .cloned();
+        ctx.traced_query("agent-attribute", &[Value::Ext(entity), attr], value)
+    }));
+    tree.register_query("global-tagged", query_fn!(ctx, tag: Value => {
+        let entities = ctx.world().global_tagged(&tag)
+            .filter(|entity| ctx.perceives(*entity))
+            .map(Value::Ext)
+            .collect::<Vec<_>>();
+        ctx.traced_query("global-tagged", &[tag], entities)
+    }));
+    tree.register_query("agent-tagged", query_fn!(ctx, tag: Value => {
+        let entities = ctx.world().agent_tagged(ctx.agent, &tag)
+            .filter(|entity| ctx.perceives(*entity))
+            .map(Value::Ext)
+            .collect::<Vec<_>>();
+        ctx.traced_query("agent-tagged", &[tag], entities)
+    }));
+    tree.register_query("lost-claims", query_fn!(ctx => {
+        let claims = ctx.world().lost_claims(ctx.agent).map(Value::Ext);
+        ctx.traced_query("lost-claims", &[], claims)
+    }));
+    tree.register_query("memory", query_fn!(ctx, key: Value => {
+        let value = ctx.world().agent_memory(ctx.agent, &key).cloned();
+        ctx.traced_query("memory", &[key], value)
+    }));
+    tree.register_query("activity", query_fn!(ctx => {
+        let world = ctx.world();
+        let activity = world.agent_activity(ctx.agent).map(|activity| {
+            let progress = activity.progress(world.tick());
+            Value::List(Arc::new([
+                activity.name.clone(),
+                Value::Int(i64::try_from(progress).unwrap_or(i64::MAX)),
+                Value::Int(i64::try_from(activity.duration).unwrap_or(i64::MAX)),
+            ]))
+        });
+        ctx.traced_query("activity", &[], activity)
+    }));
+    tree.register_query("finished-activity", query_fn!(ctx => {
+        let finished = ctx.world().agent_finished_activity(ctx.agent).map(|finished| {
+            Value::List(Arc::new([
+                finished.activity.name.clone(),
+                Value::Symbol(finished.outcome.name().into()),
+                Value::Int(i64::try_from(finished.tick).unwrap_or(i64::MAX)),
+            ]))
+        });
+        ctx.traced_query("finished-activity", &[], finished)
+    }));
 }
 
-fn setup_tree_queries(tree: &mut BehaviorTreeBuilder<Context<'_>, Entity, Effect>) {
-    tree.register_query("spaces", query_fn!(ctx => ctx.spaces().map(Value::Ext)));
+fn setup_tree_conditions(tree: &mut TreeBuilder) {
+    tree.register_condition("has-global-tag", cond_fn!(ctx, entity: Ext, tag: Value => {
+        let result = ctx.world().contains_global_tag(entity, &tag).unwrap_or(false);
+        ctx.traced_condition("has-global-tag", &[Value::Ext(entity), tag], result)
+    }));
+    tree.register_condition("has-agent-tag", cond_fn!(ctx, entity: Ext, tag: Value => {
+        let result = ctx.world().has_agent_tag(ctx.agent, entity, &tag).unwrap_or(false);
+        ctx.traced_condition("has-agent-tag", &[Value::Ext(entity), tag], result)
+    }));
 }
 
-fn setup_tree_globals(tree: &mut BehaviorTreeBuilder<Context<'_>, Entity, Effect>) {
-    tree.register_global("$^self", |ctx| Value::Ext(ctx.agent));
-    tree.register_global("$^location", |ctx| Value::Ext(ctx.location()));
-    tree.register_global("$^space", |ctx| Value::Ext(ctx.space()));
+fn setup_tree_effects(tree: &mut TreeBuilder) {
+    tree.register_effect("move-to-area", effect_fn!(_ctx, area: Ext => {
+        Effect::MoveToArea { area }
+    }));
+    tree.register_effect("traverse-portal", effect_fn!(_ctx, portal_object: Ext => {
+        Effect::TraversePortal { portal_object }
+    }));
+    tree.register_effect("move-object", effect_fn!(_ctx, object: Ext, parent: Ext => {
+        Effect::MoveObject { object, parent }
+    }));
+    tree.register_effect("set-global-attribute", effect_fn!(
+        _ctx, entity: Ext, attr: Value, value: Value => {
+            Effect::SetGlobalAttribute { entity, attr, value }
+        }
+    ));
+    tree.register_effect("clear-global-attribute", effect_fn!(_ctx, entity: Ext, attr: Value => {
+        Effect::ClearGlobalAttribute { entity, attr }
+    }));
+    tree.register_effect("set-global-tag", effect_fn!(_ctx, entity: Ext, tag: Value => {
+        Effect::SetGlobalTag { entity, tag }
+    }));
+    tree.register_effect("clear-global-tag", effect_fn!(_ctx, entity: Ext, tag: Value => {
+        Effect::ClearGlobalTag { entity, tag }
+    }));
+    tree.register_effect("set-agent-attribute", effect_fn!(
+        _ctx, entity: Ext, attr: Value, value: Value => {
+            Effect::SetAgentAttribute { entity, attr, value }
+        }
+    ));
+    tree.register_effect("clear-agent-attribute", effect_fn!(_ctx, entity: Ext, attr: Value => {
+        Effect::ClearAgentAttribute { entity, attr }
+    }));
+    tree.register_effect("set-agent-tag", effect_fn!(_ctx, entity: Ext, tag: Value => {
+        Effect::SetAgentTag { entity, tag }
+    }));
+    tree.register_effect("clear-agent-tag", effect_fn!(_ctx, entity: Ext, tag: Value => {
+        Effect::ClearAgentTag { entity, tag }
+    }));
+    tree.register_effect("remember", effect_fn!(_ctx, key: Value, value: Value => {
+        Effect::Remember { key, value }
+    }));
+    tree.register_effect("forget", effect_fn!(_ctx, key: Value => {
+        Effect::Forget { key }
+    }));
+    tree.register_effect("start-activity", effect_fn!(_ctx, name: Value, duration: Value => {
+        Effect::StartActivity { name, duration }
+    }));
+    tree.register_effect("interrupt-activity", effect_fn!(_ctx => {
+        Effect::InterruptActivity
+    }));
+    tree.register_effect("score", effect_fn!(_ctx, score: Value => {
+        Effect::Score { score }
+    }));
+}
+
+fn setup_tree_globals(tree: &mut TreeBuilder) {
+    tree.register_global("$^self", |ctx| {
+        ctx.traced_global("$^self", Value::Ext(ctx.agent))
+    });
+    tree.register_global("$^location", |ctx| {
+        ctx.traced_global("$^location", Value::Ext(ctx.location()))
+    });
+    tree.register_global("$^space", |ctx| {
+        ctx.traced_global("$^space", Value::Ext(ctx.space()))
+    });
+    tree.register_global("$^area", |ctx| {
+        ctx.traced_global("$^area", Value::Ext(ctx.area()))
+    });
+    tree.register_global("$^tick", |ctx| {
+        let tick = i64::try_from(ctx.world().tick()).expect("tick in integer range");
+        ctx.traced_global("$^tick", Value::Int(tick))
+    });
+    tree.register_global("$^time", |ctx| {
+        ctx.traced_global("$^time", Value::Float(FloatOrd(ctx.world().time())))
+    });
+    tree.register_global("$^kind", |ctx| {
+        let kind = ctx.world().layout_kind(ctx.agent).cloned()
+            .unwrap_or_else(|| Value::List(Arc::new([])));
+        ctx.traced_global("$^kind", kind)
+    });
     tree.register_global("$^position", |ctx| {
-        ctx.world.agent_position(ctx.agent).expect("invalid context position").clone()
+        let position = ctx.world().agent_position(ctx.agent)
+            .expect("invalid context position");
+        ctx.traced_global("$^position", position.clone())
     });
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Effect {
+    /// Move the agent to another area in its current space.
+    MoveToArea { area: Entity },
+    /// Move the agent from the portal object it is located at to the portal's target object.
+    TraversePortal { portal_object: Entity },
+    /// Move an object below a new parent space or object.
+    ///
+    /// Both the object and the new parent have to be in the agent's current space.
+    MoveObject { object: Entity, parent: Entity },
+    SetGlobalAttribute { entity: Entity, attr: Value, value: Value },
+    ClearGlobalAttribute { entity: Entity, attr: Value },
+    SetGlobalTag { entity: Entity, tag: Value },
+    ClearGlobalTag { entity: Entity, tag: Value },
+    /// Set an attribute on an entity local to the agent.
+    SetAgentAttribute { entity: Entity, attr: Value, value: Value },
+    /// Clear an attribute on an entity local to the agent.
+    ClearAgentAttribute { entity: Entity, attr: Value },
+    /// Set a tag on an entity local to the agent.
+    SetAgentTag { entity: Entity, tag: Value },
+    /// Clear a tag on an entity local to the agent.
+    ClearAgentTag { entity: Entity, tag: Value },
+    /// Store a value in the agent's blackboard memory.
+    Remember { key: Value, value: Value },
+    /// Remove a value from the agent's blackboard memory.
+    Forget { key: Value },
+    /// Keep the agent busy with an activity for a number of steps.
+    ///
+    /// The duration must be a non-negative integer.
+    StartActivity { name: Value, duration: Value },
+    /// Stop the activity the agent is currently busy with.
+    InterruptActivity,
+    /// Add to the score of a utility candidate. Applying it has no effect.
+    Score { score: Value },
+    /// Host defined effect.
+    Custom(CustomEffect),
+}
+
+impl Effect {
+    /// The entity this effect requires exclusive access to during a step.
+    pub fn claimed_entity(&self) -> Option<Entity> {
+        match self {
+            Self::SetGlobalAttribute { entity, .. }
+            | Self::ClearGlobalAttribute { entity, .. }
+            | Self::SetGlobalTag { entity, .. }
+            | Self::ClearGlobalTag { entity, .. }
+            | Self::MoveObject { object: entity, .. } => Some(*entity),
+            Self::Custom(custom) => custom.payload().claimed_entity(),
+            Self::MoveToArea { .. }
+            | Self::TraversePortal { .. }
+            | Self::SetAgentAttribute { .. }
+            | Self::ClearAgentAttribute { .. }
+            | Self::SetAgentTag { .. }
+            | Self::ClearAgentTag { .. }
+            | Self::Remember { .. }
+            | Self::Forget { .. }
+            | Self::StartActivity { .. }
+            | Self::InterruptActivity
+            | Self::Score { .. } => None,
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
-struct Context<'a> {
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Behavior>();
+    assert_send_sync::<Context>();
+};
+
+/// Evaluation state of a single agent, available to queries, conditions, globals and
+/// effects.
+#[derive(Debug)]
+pub struct Context {
     agent: Entity,
-    world: &'a World,
+    /// Shares its state with the evaluated world, see [`World`].
+    world: World,
     spaces: OnceCell<Arc<[Entity]>>,
+    perceivable: OnceCell<Option<EntitySet>>,
+    trace: Option<Mutex<Vec<TraceEvent>>>,
 }
 
-impl<'a> Context<'a> {
-    fn new(world: &'a World, agent: Entity) -> Self {
+impl Context {
+    fn new(world: &World, agent: Entity, traced: bool) -> Self {
         Self {
-            world,
+            world: world.clone(),
             agent,
             spaces: OnceCell::new(),
+            perceivable: OnceCell::new(),
+            trace: traced.then(Mutex::default),
         }
     }
 
-    fn location(&self) -> Entity {
-        self.world.agent_location(self.agent).expect("context agent location")
+    pub fn agent(&self) -> Entity {
+        self.agent
     }
 
-    fn space(&self) -> Entity {
-        self.world.object_space(self.location()).expect("context agent space")
+    pub fn world(&self) -> &World {
+        &self.world
     }
 
-    fn spaces(&self) -> impl Iterator<Item = Entity> + '_ {
+    pub fn location(&self) -> Entity {
+        self.world().agent_location(self.agent).expect("context agent location")
+    }
+
+    pub fn area(&self) -> Entity {
+        self.world().object_area(self.location()).expect("context agent area")
+    }
+
+    pub fn space(&self) -> Entity {
+        self.world().object_space(self.location()).expect("context agent space")
+    }
+
+    /// All reachable spaces ordered by distance.
+    pub fn spaces(&self) -> impl Iterator<Item = Entity> + '_ {
         self.spaces.get_or_init(|| {
-            self.world.spaces_by_distance(self.space()).collect()
+            self.world().spaces_by_distance(self.space()).collect()
         }).iter().copied()
     }
+
+    /// Whether the agent can perceive the entity according to the world's perception range.
+    pub fn perceives(&self, entity: Entity) -> bool {
+        self.perceivable
+            .get_or_init(|| self.world().perceivable(self.agent))
+            .as_ref()
+            .map_or(true, |perceivable| perceivable.contains(&entity))
+    }
+
+    fn trace_events(&self) -> Option<Vec<TraceEvent>> {
+        self.trace.as_ref().map(|trace| std::mem::take(&mut *trace.lock().unwrap()))
+    }
+
+    fn record(&self, event: impl FnOnce() -> TraceEvent) {
+        if let Some(trace) = &self.trace {
+            trace.lock().unwrap().push(event());
+        }
+    }
+
+    /// Records a resolved global in the agent's trace when tracing is enabled.
+    pub fn traced_global(&self, name: &str, value: Value) -> Value {
+        self.record(|| TraceEvent::Global { name: name.into(), value: value.clone() });
+        value
+    }
+
+    /// Records a condition result in the agent's trace when tracing is enabled.
+    pub fn traced_condition(&self, name: &str, arguments: &[Value], result: bool) -> bool {
+        self.record(|| TraceEvent::Condition {
+            name: name.into(),
+            arguments: arguments.into(),
+            result,
+        });
+        result
+    }
+
+    /// Records query results in the agent's trace when tracing is enabled.
+    pub fn traced_query<I>(
+        &self,
+        name: &str,
+        arguments: &[Value],
+        results: I,
+    ) -> Either<I::IntoIter, std::vec::IntoIter<Value>>
+    where
+        I: IntoIterator<Item = Value>,
+    {
+        if self.trace.is_none() {
+            return Either::Left(results.into_iter());
+        }
+        let results = results.into_iter().collect::<Vec<_>>();
+        self.record(|| TraceEvent::Query {
+            name: name.into(),
+            arguments: arguments.into(),
+            results: results.clone(),
+        });
+        Either::Right(results.into_iter())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroUsize;
+    use std::sync::Arc;
+
+    use crate::testing::{Fixture, behavior, symbol, test_indent};
+    use crate::world::entities::Entity;
+    use crate::world::perception::PerceptionRange;
+
+    use super::{Evaluation, Value};
+    use super::trace::{TraceRecorder, TraceEvent};
+
+
+    /// Runs `query` for an agent and returns the results recorded in its trace.
+    fn query_results(fixture: &Fixture, agent: &str, query: &str) -> Vec<Value> {
+        let behavior = behavior(test_indent(), &format!("
+action main
+  query {query} -> $result
+    remember result $result
+"));
+        let mut recorder = TraceRecorder::default();
+        behavior.decide_traced(fixture.world(), Evaluation::Serial, &mut recorder);
+        let agent = fixture.entity(agent);
+        let trace = recorder.ticks()[0].agents.iter()
+            .find(|trace| trace.agent == agent)
+            .expect("agent trace");
+        let name = query.split_whitespace().next().unwrap();
+        trace.events.iter().find_map(|event| match event {
+            TraceEvent::Query { name: query_name, results, .. } if query_name == name => {
+                Some(results.clone())
+            },
+            _ => None,
+        }).expect("query event")
+    }
+
+    fn entities(values: Vec<Value>) -> Vec<Entity> {
+        let mut entities = values.into_iter().map(|value| match value {
+            Value::Ext(entity) => entity,
+            other => panic!("expected entity, found {other:?}"),
+        }).collect::<Vec<_>>();
+        entities.sort();
+        entities
+    }
+
+    fn named(fixture: &Fixture, names: &[&str]) -> Vec<Entity> {
+        let mut entities = names.iter().map(|name| fixture.entity(name)).collect::<Vec<_>>();
+        entities.sort();
+        entities
+    }
+
+    fn layout_fixture() -> Fixture {
+        let mut fixture = Fixture::new();
+        fixture.space("hall", "room");
+        fixture.space("yard", "room");
+        fixture.object("hearth", "floor", "hall");
+        fixture.object("corner", "floor", "hall");
+        fixture.object("lawn", "floor", "yard");
+        fixture.object("chest", "chest", "hearth");
+        fixture.object("coin", "coin", "chest");
+        fixture.portal("door", "door", ("hall", "yard"));
+        fixture.agent("self", "walker", "hearth");
+        fixture.agent("near", "walker", "hearth");
+        fixture.agent("far", "walker", "corner");
+        fixture.agent("outside", "walker", "lawn");
+        fixture
+    }
+
+    #[test]
+    fn areas_query() {
+        let fixture = layout_fixture();
+        assert_eq!(
+            entities(query_results(&fixture, "self", "areas $^space")),
+            named(&fixture, &["hearth", "corner", "door@hall"]),
+        );
+        assert!(query_results(&fixture, "self", "areas $^location").is_empty());
+    }
+
+    #[test]
+    fn child_objects_query() {
+        let fixture = layout_fixture();
+        assert_eq!(
+            entities(query_results(&fixture, "self", "child-objects $^location")),
+            named(&fixture, &["chest"]),
+        );
+        assert!(query_results(&fixture, "outside", "child-objects $^location").is_empty());
+    }
+
+    #[test]
+    fn portals_query() {
+        let fixture = layout_fixture();
+        assert_eq!(query_results(&fixture, "self", "portals"), [
+            Value::List(Arc::new([
+                Value::Ext(fixture.entity("door@hall")),
+                Value::Ext(fixture.entity("yard")),
+            ])),
+        ]);
+    }
+
+    #[test]
+    fn area_agents_query() {
+        let fixture = layout_fixture();
+        assert_eq!(
+            entities(query_results(&fixture, "self", "area-agents")),
+            named(&fixture, &["near"]),
+        );
+        assert!(query_results(&fixture, "outside", "area-agents").is_empty());
+    }
+
+    #[test]
+    fn space_agents_query() {
+        let fixture = layout_fixture();
+        assert_eq!(
+            entities(query_results(&fixture, "self", "space-agents")),
+            named(&fixture, &["near", "far"]),
+        );
+        assert!(query_results(&fixture, "outside", "space-agents").is_empty());
+    }
+
+    const PERCEPTION_TAGGED: &[&str] = &[
+        "hall", "yard", "field", "hearth", "corner", "chest", "lawn", "meadow",
+        "door", "door@hall", "door@yard", "gate", "gate@yard", "gate@field",
+        "self", "near", "far", "outside", "distant",
+    ];
+
+    fn perception_fixture() -> Fixture {
+        let mut fixture = Fixture::new();
+        fixture.space("hall", "room");
+        fixture.space("yard", "room");
+        fixture.space("field", "room");
+        fixture.object("hearth", "floor", "hall");
+        fixture.object("corner", "floor", "hall");
+        fixture.object("chest", "chest", "hearth");
+        fixture.object("lawn", "floor", "yard");
+        fixture.object("meadow", "floor", "field");
+        fixture.portal("door", "door", ("hall", "yard"));
+        fixture.portal("gate", "gate", ("yard", "field"));
+        fixture.agent("self", "walker", "hearth");
+        fixture.agent("near", "walker", "hearth");
+        fixture.agent("far", "walker", "corner");
+        fixture.agent("outside", "walker", "lawn");
+        fixture.agent("distant", "walker", "meadow");
+        for name in PERCEPTION_TAGGED {
+            let entity = fixture.entity(name);
+            fixture.world_mut().set_global_tag(entity, symbol("thing")).unwrap();
+        }
+        fixture
+    }
+
+    /// Tagged entities perceived by `self` with the given perception range.
+    fn perceived(fixture: &mut Fixture, range: PerceptionRange) -> Vec<Entity> {
+        fixture.world_mut().set_perception_range(range);
+        entities(query_results(fixture, "self", "global-tagged thing"))
+    }
+
+    const SPACE_PERCEIVED: &[&str] = &[
+        "hall", "hearth", "corner", "chest", "door", "door@hall", "self", "near", "far",
+    ];
+
+    #[test]
+    fn area_perception() {
+        let mut fixture = perception_fixture();
+        assert_eq!(
+            perceived(&mut fixture, PerceptionRange::Area),
+            named(&fixture, &["hall", "hearth", "chest", "self", "near"]),
+        );
+        assert!(query_results(&fixture, "self", "spaces").is_empty());
+        assert_eq!(
+            entities(query_results(&fixture, "self", "child-objects $^location")),
+            named(&fixture, &["chest"]),
+        );
+        assert_eq!(
+            entities(query_results(&fixture, "self", "areas $^space")),
+            named(&fixture, &["hearth"]),
+        );
+    }
+
+    #[test]
+    fn space_perception() {
+        let mut fixture = perception_fixture();
+        assert_eq!(
+            perceived(&mut fixture, PerceptionRange::Space),
+            named(&fixture, SPACE_PERCEIVED),
+        );
+        assert!(query_results(&fixture, "self", "spaces").is_empty());
+        assert_eq!(
+            entities(query_results(&fixture, "self", "space-agents")),
+            named(&fixture, &["near", "far"]),
+        );
+    }
+
+    #[test]
+    fn portal_perception() {
+        let mut fixture = perception_fixture();
+        assert_eq!(perceived(&mut fixture, PerceptionRange::Portals(0)), named(&fixture, SPACE_PERCEIVED));
+        assert_eq!(
+            perceived(&mut fixture, PerceptionRange::Portals(1)),
+            named(&fixture, &[
+                "hall", "yard", "hearth", "corner", "chest", "lawn",
+                "door", "door@hall", "door@yard", "gate", "gate@yard",
+                "self", "near", "far", "outside",
+            ]),
+        );
+        assert_eq!(
+            entities(query_results(&fixture, "self", "spaces")),
+            named(&fixture, &["yard"]),
+        );
+        assert_eq!(
+            perceived(&mut fixture, PerceptionRange::Portals(2)),
+            named(&fixture, PERCEPTION_TAGGED),
+        );
+
+        let door = fixture.entity("door");
+        fixture.world_mut().close_portal(door);
+        assert_eq!(
+            perceived(&mut fixture, PerceptionRange::Portals(2)),
+            named(&fixture, SPACE_PERCEIVED),
+        );
+        assert!(query_results(&fixture, "self", "spaces").is_empty());
+    }
+
+
+    #[test]
+    fn parallel_evaluation_matches_serial() {
+        let mut fixture = Fixture::new();
+        fixture.space("hall", "room");
+        fixture.space("yard", "room");
+        fixture.portal("door", "door", ("hall", "yard"));
+        for index in 0..4 {
+            fixture.object(&format!("hall-{index}"), "floor", "hall");
+            fixture.object(&format!("yard-{index}"), "floor", "yard");
+        }
+        for index in 0..16 {
+            let location = match index % 3 {
+                0 => format!("hall-{}", index % 4),
+                1 => format!("yard-{}", index % 4),
+                _ => String::from("door@hall"),
+            };
+            fixture.agent(&format!("agent-{index}"), "walker", &location);
+        }
+        let behavior = behavior(test_indent(), "
+action main
+  remember location $^location
+  remember space $^space
+");
+        let world = fixture.world();
+        let serial = behavior.decide_with(world, Evaluation::Serial);
+        for threads in [1, 3, 16, 64] {
+            let threads = NonZeroUsize::new(threads).unwrap();
+            let parallel = behavior.decide_with(world, Evaluation::Parallel { threads });
+            assert_eq!(
+                serial.iter().collect::<Vec<_>>(),
+                parallel.iter().collect::<Vec<_>>(),
+            );
+        }
+        assert_eq!(serial.iter().count(), 16);
+    }
 }
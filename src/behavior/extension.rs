@@ -0,0 +1,134 @@
+use std::any::Any;
+use std::fmt;
+use std::sync::Arc;
+
+use reagenz::{BehaviorTreeBuilder, ScriptSource, CompileError};
+use treelang::Indent;
+
+use crate::world::World;
+use crate::world::effects::EffectResult;
+use crate::world::entities::Entity;
+
+use super::{Behavior, Context, Effect};
+
+
+pub type TreeBuilder = BehaviorTreeBuilder<Context, Entity, Effect>;
+
+/// Host provided additions to the queries, conditions, globals and effects of a behavior.
+///
+/// Extensions are applied after the built-in registrations every time the behavior is
+/// compiled, including on reload.
+pub trait BehaviorExtension: Send + Sync {
+    fn setup(&self, tree: &mut TreeBuilder);
+}
+
+impl<F> BehaviorExtension for F
+where
+    F: Fn(&mut TreeBuilder) + Send + Sync,
+{
+    fn setup(&self, tree: &mut TreeBuilder) {
+        self(tree)
+    }
+}
+
+pub struct BehaviorBuilder {
+    indent: Indent,
+    sources: Vec<ScriptSource>,
+    extensions: Vec<Arc<dyn BehaviorExtension>>,
+}
+
+impl BehaviorBuilder {
+    pub fn new(indent: Indent) -> Self {
+        Self {
+            indent,
+            sources: Vec::new(),
+            extensions: Vec::new(),
+        }
+    }
+
+    pub fn source(mut self, source: ScriptSource) -> Self {
+        self.sources.push(source);
+        self
+    }
+
+    pub fn sources<I>(mut self, sources: I) -> Self
+    where
+        I: IntoIterator<Item = ScriptSource>,
+    {
+        self.sources.extend(sources);
+        self
+    }
+
+    pub fn extension<E>(mut self, extension: E) -> Self
+    where
+        E: BehaviorExtension + 'static,
+    {
+        self.extensions.push(Arc::new(extension));
+        self
+    }
+
+    pub fn build(self) -> Result<Behavior, CompileError> {
+        Behavior::compile(self.indent, self.sources, self.extensions)
+    }
+}
+
+/// Host defined effect payload.
+pub trait EffectPayload: fmt::Debug + Send + Sync + 'static {
+    /// Translates the payload into built-in effects when it is applied.
+    ///
+    /// The expanded effects are part of the agent's transaction.
+    fn expand(&self, world: &World, agent: Entity) -> EffectResult<Vec<Effect>>;
+
+    /// The entity this effect requires exclusive access to during a step.
+    fn claimed_entity(&self) -> Option<Entity> {
+        None
+    }
+
+    fn as_any(&self) -> &dyn Any;
+
+    /// Compares with another payload, usually by downcasting it through
+    /// [`EffectPayload::as_any`] and comparing it with `==`.
+    fn dyn_eq(&self, other: &dyn EffectPayload) -> bool;
+}
+
+/// Shared handle to a host defined effect payload.
+///
+/// Two custom effects are equal if their payloads compare equal.
+#[derive(Clone)]
+pub struct CustomEffect {
+    payload: Arc<dyn EffectPayload>,
+}
+
+impl CustomEffect {
+    pub fn new<P>(payload: P) -> Self
+    where
+        P: EffectPayload,
+    {
+        Self { payload: Arc::new(payload) }
+    }
+
+    pub fn payload(&self) -> &dyn EffectPayload {
+        &*self.payload
+    }
+
+    pub fn downcast_ref<P>(&self) -> Option<&P>
+    where
+        P: EffectPayload,
+    {
+        self.payload.as_any().downcast_ref()
+    }
+}
+
+impl fmt::Debug for CustomEffect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.payload.fmt(f)
+    }
+}
+
+impl PartialEq for CustomEffect {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.payload, &other.payload) || self.payload.dyn_eq(other.payload())
+    }
+}
+
+impl Eq for CustomEffect {}
\ No newline at end of file
@@ -0,0 +1,88 @@
+use fnv::FnvHashMap;
+use smol_str::SmolStr;
+
+use crate::world::World;
+use crate::world::entities::Entity;
+
+use super::{Value, ROOT_ACTION};
+
+
+/// Chooses the action an agent starts evaluating from.
+///
+/// An explicit root set on the world takes precedence, followed by the first matching
+/// global tag of the agent, the agent's layout kind, and finally the default root.
+///
+/// Agents busy with an activity only evaluate the interrupt root, if one is set. When it
+/// succeeds, the activity is interrupted and the root's effects are applied.
+#[derive(Debug, Clone)]
+pub struct RootSelector {
+    default_root: SmolStr,
+    kind_roots: FnvHashMap<Value, SmolStr>,
+    tag_roots: Vec<(Value, SmolStr)>,
+    interrupt_root: Option<SmolStr>,
+}
+
+impl Default for RootSelector {
+    fn default() -> Self {
+        Self {
+            default_root: ROOT_ACTION.into(),
+            kind_roots: FnvHashMap::default(),
+            tag_roots: Vec::new(),
+            interrupt_root: None,
+        }
+    }
+}
+
+impl RootSelector {
+    pub fn set_default_root<R>(&mut self, root: R)
+    where
+        R: Into<SmolStr>,
+    {
+        self.default_root = root.into();
+    }
+
+    pub fn set_kind_root<R>(&mut self, kind: Value, root: R)
+    where
+        R: Into<SmolStr>,
+    {
+        self.kind_roots.insert(kind, root.into());
+    }
+
+    /// Adds a root for agents with a global tag. Earlier tags take precedence.
+    pub fn add_tag_root<R>(&mut self, tag: Value, root: R)
+    where
+        R: Into<SmolStr>,
+    {
+        self.tag_roots.push((tag, root.into()));
+    }
+
+    pub fn set_interrupt_root<R>(&mut self, root: R)
+    where
+        R: Into<SmolStr>,
+    {
+        self.interrupt_root = Some(root.into());
+    }
+
+    pub fn clear_interrupt_root(&mut self) {
+        self.interrupt_root = None;
+    }
+
+    pub fn interrupt_root(&self) -> Option<&SmolStr> {
+        self.interrupt_root.as_ref()
+    }
+
+    pub fn select<'a>(&'a self, world: &'a World, agent: Entity) -> &'a SmolStr {
+        if let Some(root) = world.agent_root(agent) {
+            return root;
+        }
+        let tag_root = self.tag_roots.iter().find(|(tag, _)| {
+            world.contains_global_tag(agent, tag).unwrap_or(false)
+        });
+        if let Some((_, root)) = tag_root {
+            return root;
+        }
+        world.layout_kind(agent)
+            .and_then(|kind| self.kind_roots.get(kind))
+            .unwrap_or(&self.default_root)
+    }
+}
\ No newline at end of file
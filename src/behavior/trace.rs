@@ -0,0 +1,141 @@
+use std::fmt;
+
+use float_ord::FloatOrd;
+use smol_str::SmolStr;
+
+use crate::world::entities::Entity;
+
+use super::{Value, Effect};
+
+
+/// Recorded evaluations over multiple decision phases.
+#[derive(Debug, Clone, Default)]
+pub struct TraceRecorder {
+    ticks: Vec<TickTrace>,
+}
+
+impl TraceRecorder {
+    pub fn ticks(&self) -> &[TickTrace] {
+        &self.ticks
+    }
+
+    pub fn clear(&mut self) {
+        self.ticks.clear();
+    }
+
+    pub(super) fn push_tick(&mut self, tick: u64, agents: Vec<AgentTrace>) {
+        self.ticks.push(TickTrace { tick, agents });
+    }
+}
+
+/// All agent evaluations of a single decision phase.
+#[derive(Debug, Clone)]
+pub struct TickTrace {
+    /// The world tick the decisions were made at.
+    pub tick: u64,
+    pub agents: Vec<AgentTrace>,
+}
+
+#[derive(Debug, Clone)]
+pub struct AgentTrace {
+    pub agent: Entity,
+    pub location: Entity,
+    pub space: Entity,
+    pub root: SmolStr,
+    pub events: Vec<TraceEvent>,
+    /// The emitted effects, or `None` if the root action failed.
+    pub effects: Option<Vec<Effect>>,
+}
+
+/// A single step of an agent's evaluation.
+///
+/// Actions are recorded when the engine runs them as a root, utility scorer or utility
+/// candidate. Nodes nested inside an action are only visible through the globals, queries
+/// and conditions they use, since the behavior tree does not report them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceEvent {
+    Action { name: SmolStr, succeeded: bool },
+    Global { name: SmolStr, value: Value },
+    Query { name: SmolStr, arguments: Vec<Value>, results: Vec<Value> },
+    Condition { name: SmolStr, arguments: Vec<Value>, result: bool },
+    /// Scores of all candidates of a utility selector and the candidate that ran.
+    Utility {
+        selector: SmolStr,
+        scores: Vec<(SmolStr, Option<FloatOrd<f64>>)>,
+        chosen: Option<SmolStr>,
+    },
+}
+
+impl fmt::Display for TraceRecorder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for tick in &self.ticks {
+            write!(f, "{tick}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for TickTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "tick {}", self.tick)?;
+        for agent in &self.agents {
+            write!(f, "{agent}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for AgentTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "  agent {:?} at {:?} in {:?}", self.agent, self.location, self.space)?;
+        writeln!(f, "    root {}", self.root)?;
+        for event in &self.events {
+            writeln!(f, "      {event}")?;
+        }
+        match &self.effects {
+            Some(effects) => {
+                writeln!(f, "    success")?;
+                for effect in effects {
+                    writeln!(f, "      effect {effect:?}")?;
+                }
+            },
+            None => {
+                writeln!(f, "    failure")?;
+            },
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for TraceEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Action { name, succeeded } => {
+                let outcome = if *succeeded { "success" } else { "failure" };
+                write!(f, "action {name} -> {outcome}")
+            },
+            Self::Global { name, value } => {
+                write!(f, "global {name} = {value:?}")
+            },
+            Self::Query { name, arguments, results } => {
+                write!(f, "query {name} {arguments:?} -> {results:?}")
+            },
+            Self::Condition { name, arguments, result } => {
+                write!(f, "condition {name} {arguments:?} -> {result}")
+            },
+            Self::Utility { selector, scores, chosen } => {
+                write!(f, "utility {selector}")?;
+                for (action, score) in scores {
+                    match score {
+                        Some(FloatOrd(score)) => write!(f, " {action}={score}")?,
+                        None => write!(f, " {action}=failed")?,
+                    }
+                }
+                match chosen {
+                    Some(action) => write!(f, " -> {action}"),
+                    None => write!(f, " -> none"),
+                }
+            },
+        }
+    }
+}
\ No newline at end of file
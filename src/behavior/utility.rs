@@ -0,0 +1,220 @@
+use std::hash::{Hash, Hasher};
+
+use float_ord::FloatOrd;
+use fnv::FnvHasher;
+use reagenz::BehaviorTree;
+use smol_str::SmolStr;
+
+use crate::world::entities::Entity;
+
+use super::{Context, Effect, Value, evaluate_action};
+use super::trace::TraceEvent;
+
+
+/// Picks between candidate actions by the scores their scoring actions assign.
+///
+/// A candidate's score is the sum of the `score` effects its scoring action emits. Only
+/// effects of the branches that succeeded count, and candidates whose scoring action fails
+/// or emits a non-numeric score are not considered.
+#[derive(Debug, Clone, Default)]
+pub struct UtilitySelector {
+    candidates: Vec<UtilityCandidate>,
+    choice: UtilityChoice,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UtilityCandidate {
+    pub action: SmolStr,
+    pub scorer: SmolStr,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UtilityChoice {
+    /// Run the highest scoring candidate, falling back to lower scores if it fails.
+    #[default]
+    Best,
+    /// Pick a positively scored candidate with a probability proportional to its score.
+    ///
+    /// If it fails, the remaining positively scored candidates are drawn the same way,
+    /// followed by the others in score order.
+    Weighted { seed: u64 },
+}
+
+impl UtilitySelector {
+    pub fn new(choice: UtilityChoice) -> Self {
+        Self { candidates: Vec::new(), choice }
+    }
+
+    pub fn add_candidate<A, S>(&mut self, action: A, scorer: S)
+    where
+        A: Into<SmolStr>,
+        S: Into<SmolStr>,
+    {
+        self.candidates.push(UtilityCandidate { action: action.into(), scorer: scorer.into() });
+    }
+
+    pub fn candidates(&self) -> &[UtilityCandidate] {
+        &self.candidates
+    }
+
+    pub(super) fn evaluate(
+        &self,
+        tree: &BehaviorTree<Context, Entity, Effect>,
+        ctx: &Context,
+        name: &SmolStr,
+    ) -> Option<Vec<Effect>> {
+        let scores = self.candidates.iter().map(|candidate| {
+            total_score(&evaluate_action(tree, ctx, &candidate.scorer)?)
+        }).collect::<Vec<_>>();
+        let mut ranked = self.candidates.iter().zip(scores.iter().copied())
+            .filter_map(|(candidate, score)| Some((candidate, score?)))
+            .collect::<Vec<_>>();
+        ranked.sort_by_key(|(_, score)| std::cmp::Reverse(FloatOrd(*score)));
+        if let UtilityChoice::Weighted { seed } = self.choice {
+            ranked = self.draw_order(ctx, seed, &ranked);
+        }
+        let mut chosen = None;
+        let mut effects = None;
+        for (candidate, _) in ranked {
+            if let Some(candidate_effects) = evaluate_action(tree, ctx, &candidate.action) {
+                chosen = Some(candidate.action.clone());
+                effects = Some(candidate_effects);
+                break;
+            }
+        }
+        ctx.record(|| TraceEvent::Utility {
+            selector: name.clone(),
+            scores: self.candidates.iter().zip(scores)
+                .map(|(candidate, score)| (candidate.action.clone(), score.map(FloatOrd)))
+                .collect(),
+            chosen,
+        });
+        effects
+    }
+
+    /// Orders candidates by repeated weighted draws without replacement, followed by the
+    /// candidates without a positive score.
+    fn draw_order<'a>(
+        &self,
+        ctx: &Context,
+        seed: u64,
+        ranked: &[(&'a UtilityCandidate, f64)],
+    ) -> Vec<(&'a UtilityCandidate, f64)> {
+        let (mut positive, rest): (Vec<_>, Vec<_>) = ranked.iter().copied()
+            .partition(|(_, score)| *score > 0.0);
+        let mut hasher = FnvHasher::with_key(seed);
+        (ctx.world().tick(), ctx.agent()).hash(&mut hasher);
+        let mut order = Vec::with_capacity(ranked.len());
+        while !positive.is_empty() {
+            let total = positive.iter().map(|(_, score)| *score).sum::<f64>();
+            order.len().hash(&mut hasher);
+            let mut remaining = (hasher.finish() as f64 / u64::MAX as f64) * total;
+            let index = positive.iter()
+                .position(|(_, score)| {
+                    if remaining < *score {
+                        return true;
+                    }
+                    remaining -= score;
+                    false
+                })
+                .unwrap_or(positive.len() - 1);
+            order.push(positive.remove(index));
+        }
+        order.extend(rest);
+        order
+    }
+}
+
+/// Sums the `score` effects emitted by a scoring action.
+fn total_score(effects: &[Effect]) -> Option<f64> {
+    effects.iter().try_fold(0.0, |total, effect| match effect {
+        Effect::Score { score: Value::Int(score) } => Some(total + *score as f64),
+        Effect::Score { score: Value::Float(FloatOrd(score)) } => Some(total + score),
+        Effect::Score { .. } => None,
+        _ => Some(total),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::behavior::{Behavior, Effect, Value};
+    use crate::testing::{Fixture, behavior, symbol, test_indent};
+
+    use super::{UtilityChoice, UtilitySelector};
+
+
+    const CANDIDATES: &str = "
+action score-low
+  score 1
+action score-high
+  score 2
+  score 1
+action score-blocked
+  score 10
+action score-zero
+  score 0
+action do-low
+  remember choice low
+action do-high
+  remember choice high
+action do-blocked
+  has-global-tag $^self blocked
+  remember choice blocked
+action do-zero
+  remember choice zero
+";
+
+    fn utility_behavior(choice: UtilityChoice, candidates: &[&str]) -> Behavior {
+        let mut behavior = behavior(test_indent(), CANDIDATES);
+        let mut selector = UtilitySelector::new(choice);
+        for candidate in candidates {
+            selector.add_candidate(format!("do-{candidate}"), format!("score-{candidate}"));
+        }
+        behavior.set_utility_selector("main", selector);
+        behavior
+    }
+
+    fn fixture() -> Fixture {
+        let mut fixture = Fixture::new();
+        fixture.space("hall", "room");
+        fixture.object("hearth", "floor", "hall");
+        fixture.agent("walker", "walker", "hearth");
+        fixture
+    }
+
+    fn chosen(fixture: &Fixture, behavior: &Behavior) -> Value {
+        match fixture.evaluate(behavior, "walker").as_slice() {
+            [Effect::Remember { value, .. }] => value.clone(),
+            other => panic!("unexpected effects {other:?}"),
+        }
+    }
+
+    #[test]
+    fn best_choice() {
+        let fixture = fixture();
+        let behavior = utility_behavior(UtilityChoice::Best, &["low", "high", "zero"]);
+        assert_eq!(chosen(&fixture, &behavior), symbol("high"));
+        let behavior = utility_behavior(UtilityChoice::Best, &["low", "blocked", "zero"]);
+        assert_eq!(chosen(&fixture, &behavior), symbol("low"));
+    }
+
+    #[test]
+    fn weighted_choice() {
+        let mut fixture = fixture();
+        let behavior = utility_behavior(
+            UtilityChoice::Weighted { seed: 7 },
+            &["zero", "blocked", "low", "high"],
+        );
+        let mut choices = Vec::new();
+        for _ in 0..32 {
+            let choice = chosen(&fixture, &behavior);
+            if !choices.contains(&choice) {
+                choices.push(choice);
+            }
+            fixture.step(&behavior);
+        }
+        assert_eq!(choices.len(), 2);
+        assert!(choices.contains(&symbol("low")));
+        assert!(choices.contains(&symbol("high")));
+    }
+}
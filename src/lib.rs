@@ -4,4 +4,6 @@ mod util;
 
 pub mod world;
 pub mod behavior;
-pub mod data;
\ No newline at end of file
+pub mod data;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
\ No newline at end of file
@@ -0,0 +1,207 @@
+use fnv::FnvHashMap;
+use reagenz::ScriptSource;
+use smol_str::SmolStr;
+use treelang::Indent;
+
+use crate::behavior::{Behavior, Effect, Value};
+use crate::world::World;
+use crate::world::effects::EffectFailure;
+use crate::world::entities::Entity;
+use crate::world::step::StepReport;
+
+
+/// Builds a symbol value.
+pub fn symbol(name: &str) -> Value {
+    Value::Symbol(name.into())
+}
+
+/// Compiles a behavior from a single script, panicking on compile errors.
+pub fn behavior(indent: Indent, script: &str) -> Behavior {
+    let source = ScriptSource::Str { content: script.into(), name: "fixture".into() };
+    match Behavior::load(indent, [source]) {
+        Ok(behavior) => behavior,
+        Err(error) => panic!("fixture behavior failed to compile: {error}"),
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn test_indent() -> Indent {
+    Indent::spaces(2)
+}
+
+/// A world with named entities for testing behavior scripts.
+///
+/// Entity kinds are given as symbol names. The two objects of a portal named `door`
+/// between spaces `a` and `b` are available as `door@a` and `door@b`. If a portal connects
+/// a space `a` with itself, its objects are `door@a` and `door@a#2`.
+#[derive(Debug, Clone, Default)]
+pub struct Fixture {
+    world: World,
+    names: FnvHashMap<SmolStr, Entity>,
+}
+
+impl Fixture {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    pub fn world_mut(&mut self) -> &mut World {
+        &mut self.world
+    }
+
+    /// Looks up a named entity, panicking if it does not exist.
+    pub fn entity(&self, name: &str) -> Entity {
+        match self.names.get(name) {
+            Some(entity) => *entity,
+            None => panic!("unknown fixture entity `{name}`"),
+        }
+    }
+
+    pub fn space(&mut self, name: &str, kind: &str) -> Entity {
+        let space = self.world.create_space(symbol(kind));
+        self.name(name, space)
+    }
+
+    pub fn object(&mut self, name: &str, kind: &str, parent: &str) -> Entity {
+        let object = self.world.create_object(symbol(kind), self.entity(parent));
+        self.name(name, object)
+    }
+
+    pub fn portal(&mut self, name: &str, kind: &str, (a, b): (&str, &str)) -> Entity {
+        let (space_a, space_b) = (self.entity(a), self.entity(b));
+        let portal = self.world.create_portal(symbol(kind), (space_a, space_b));
+        let mut objects = self.world.portal_objects(portal).collect::<Vec<_>>();
+        objects.sort();
+        let object_b_name = if space_a == space_b {
+            format!("{name}@{b}#2")
+        } else {
+            format!("{name}@{b}")
+        };
+        self.name(&format!("{name}@{a}"), objects[0]);
+        self.name(&object_b_name, objects[1]);
+        self.name(name, portal)
+    }
+
+    pub fn agent(&mut self, name: &str, kind: &str, location: &str) -> Entity {
+        let agent = self.world.create_agent_with_kind(symbol(kind), self.entity(location));
+        self.name(name, agent)
+    }
+
+    /// Evaluates a single agent against the current world state.
+    pub fn evaluate(&self, behavior: &Behavior, agent: &str) -> Vec<Effect> {
+        behavior.decide_agent(&self.world, self.entity(agent))
+    }
+
+    /// Applies effects for an agent as a single transaction.
+    pub fn apply(&mut self, agent: &str, effects: &[Effect]) -> Result<(), EffectFailure> {
+        let agent = self.entity(agent);
+        self.world.apply_effects(agent, effects)
+    }
+
+    pub fn step(&mut self, behavior: &Behavior) -> StepReport {
+        self.world.step(behavior)
+    }
+
+    fn name(&mut self, name: &str, entity: Entity) -> Entity {
+        assert!(
+            self.names.insert(name.into(), entity).is_none(),
+            "duplicate fixture entity `{name}`",
+        );
+        self.world.set_identifier(entity, name);
+        entity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::behavior::{Effect, Value};
+
+    use super::{Fixture, behavior, symbol, test_indent};
+
+
+    const REMEMBER_LOCATION: &str = "
+action main
+  remember location $^location
+";
+
+    #[test]
+    fn names_fixture_entities() {
+        let mut fixture = Fixture::new();
+        let hall = fixture.space("hall", "room");
+        let yard = fixture.space("yard", "room");
+        let hearth = fixture.object("hearth", "floor", "hall");
+        fixture.portal("door", "door", ("hall", "yard"));
+        let walker = fixture.agent("walker", "walker", "hearth");
+
+        let world = fixture.world();
+        assert_eq!(fixture.entity("hall"), hall);
+        assert_eq!(world.identifier(hearth).map(|name| name.as_str()), Some("hearth"));
+        assert_eq!(world.object_space(fixture.entity("door@hall")), Some(hall));
+        assert_eq!(world.object_space(fixture.entity("door@yard")), Some(yard));
+        assert_eq!(world.agent_location(walker), Some(hearth));
+        assert_eq!(world.layout_kind(walker), Some(&symbol("walker")));
+    }
+
+    #[test]
+    fn names_objects_of_portals_within_a_space() {
+        let mut fixture = Fixture::new();
+        let hall = fixture.space("hall", "room");
+        let stairs = fixture.portal("stairs", "stairs", ("hall", "hall"));
+
+        let world = fixture.world();
+        let first = fixture.entity("stairs@hall");
+        let second = fixture.entity("stairs@hall#2");
+        assert_ne!(first, second);
+        for object in [first, second] {
+            assert_eq!(world.object_space(object), Some(hall));
+            assert_eq!(world.object_portal(object), Some(stairs));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "duplicate fixture entity")]
+    fn rejects_duplicate_names() {
+        let mut fixture = Fixture::new();
+        fixture.space("hall", "room");
+        fixture.space("hall", "room");
+    }
+
+    #[test]
+    fn evaluates_and_applies_scripts() {
+        let mut fixture = Fixture::new();
+        fixture.space("hall", "room");
+        let hearth = fixture.object("hearth", "floor", "hall");
+        let walker = fixture.agent("walker", "walker", "hearth");
+        let behavior = behavior(test_indent(), REMEMBER_LOCATION);
+
+        let effects = fixture.evaluate(&behavior, "walker");
+        assert_eq!(effects, [Effect::Remember {
+            key: symbol("location"),
+            value: Value::Ext(hearth),
+        }]);
+        assert_eq!(fixture.world().agent_memory(walker, &symbol("location")), None);
+
+        fixture.apply("walker", &effects).unwrap();
+        let memory = fixture.world().agent_memory(walker, &symbol("location"));
+        assert_eq!(memory, Some(&Value::Ext(hearth)));
+    }
+
+    #[test]
+    fn steps_the_world() {
+        let mut fixture = Fixture::new();
+        fixture.space("hall", "room");
+        let hearth = fixture.object("hearth", "floor", "hall");
+        let walker = fixture.agent("walker", "walker", "hearth");
+        let behavior = behavior(test_indent(), REMEMBER_LOCATION);
+
+        let report = fixture.step(&behavior);
+        assert!(report.rejected.is_empty());
+        assert_eq!(fixture.world().tick(), 1);
+        let memory = fixture.world().agent_memory(walker, &symbol("location"));
+        assert_eq!(memory, Some(&Value::Ext(hearth)));
+    }
+}
@@ -1,3 +1,7 @@
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+
 use either::Either;
 
 
@@ -27,4 +31,42 @@ macro_rules! fn_enum_is_variant {
     }
 }
 
-pub(crate) use fn_enum_is_variant;
\ No newline at end of file
+pub(crate) use fn_enum_is_variant;
+
+/// Copy-on-write value that is cheap to clone.
+///
+/// Mutable access copies the value first if it is still shared with a clone.
+#[derive(Default)]
+pub struct Shared<T>(Arc<T>);
+
+impl<T> Clone for Shared<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T> fmt::Debug for Shared<T>
+where
+    T: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl<T> Deref for Shared<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Shared<T>
+where
+    T: Clone,
+{
+    fn deref_mut(&mut self) -> &mut T {
+        Arc::make_mut(&mut self.0)
+    }
+}
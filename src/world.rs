@@ -1,18 +1,34 @@
+use crate::util::Shared;
 
+use self::activities::WorldActivities;
 use self::agents::WorldAgents;
 use self::entities::WorldEntities;
 use self::layout::WorldLayout;
+use self::perception::WorldPerception;
+use self::step::WorldStep;
 
 
 pub mod entities;
 pub mod layout;
 pub mod agents;
+pub mod effects;
+pub mod step;
+pub mod conflicts;
+pub mod activities;
+pub mod perception;
+pub mod routes;
 
+/// The simulated world.
+///
+/// Cloning a world is cheap, clones share their state until one of them is modified.
 #[derive(Debug, Clone, Default)]
 pub struct World {
-    entities: WorldEntities,
-    layout: WorldLayout,
-    agents: WorldAgents,
+    entities: Shared<WorldEntities>,
+    layout: Shared<WorldLayout>,
+    agents: Shared<WorldAgents>,
+    step: Shared<WorldStep>,
+    activities: Shared<WorldActivities>,
+    perception: Shared<WorldPerception>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
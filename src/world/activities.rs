@@ -0,0 +1,103 @@
+use fnv::FnvHashMap;
+
+use crate::behavior::Value;
+
+use super::World;
+use super::entities::Entity;
+
+
+#[derive(Debug, Clone, Default)]
+pub(super) struct WorldActivities {
+    active: FnvHashMap<Entity, Activity>,
+    finished: FnvHashMap<Entity, FinishedActivity>,
+}
+
+/// An action spanning multiple steps.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Activity {
+    pub name: Value,
+    pub started: u64,
+    pub duration: u64,
+}
+
+impl Activity {
+    pub fn progress(&self, tick: u64) -> u64 {
+        tick.saturating_sub(self.started).min(self.duration)
+    }
+
+    pub fn is_complete(&self, tick: u64) -> bool {
+        self.progress(tick) >= self.duration
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FinishedActivity {
+    pub activity: Activity,
+    pub outcome: ActivityOutcome,
+    pub tick: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ActivityOutcome {
+    Completed,
+    Interrupted,
+}
+
+impl ActivityOutcome {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Completed => "completed",
+            Self::Interrupted => "interrupted",
+        }
+    }
+}
+
+impl World {
+    /// The activity the agent is currently busy with.
+    pub fn agent_activity(&self, agent: Entity) -> Option<&Activity> {
+        self.activities.active.get(&agent)
+    }
+
+    /// The last activity the agent completed or had interrupted.
+    pub fn agent_finished_activity(&self, agent: Entity) -> Option<&FinishedActivity> {
+        self.activities.finished.get(&agent)
+    }
+
+    pub(super) fn replace_agent_activity(
+        &mut self,
+        agent: Entity,
+        active: Option<Activity>,
+        finished: Option<FinishedActivity>,
+    ) -> (Option<Activity>, Option<FinishedActivity>) {
+        let previous_active = match active {
+            Some(active) => self.activities.active.insert(agent, active),
+            None => self.activities.active.remove(&agent),
+        };
+        let previous_finished = match finished {
+            Some(finished) => self.activities.finished.insert(agent, finished),
+            None => self.activities.finished.remove(&agent),
+        };
+        (previous_active, previous_finished)
+    }
+
+    pub(super) fn remove_agent_activities(&mut self, agent: Entity) {
+        self.activities.active.remove(&agent);
+        self.activities.finished.remove(&agent);
+    }
+
+    pub(super) fn complete_activities(&mut self) {
+        let tick = self.tick();
+        let completed = self.activities.active.iter()
+            .filter(|(_, activity)| activity.is_complete(tick))
+            .map(|(agent, _)| *agent)
+            .collect::<Vec<_>>();
+        for agent in completed {
+            let activity = self.activities.active.remove(&agent).unwrap();
+            self.activities.finished.insert(agent, FinishedActivity {
+                activity,
+                outcome: ActivityOutcome::Completed,
+                tick,
+            });
+        }
+    }
+}
\ No newline at end of file
@@ -1,6 +1,8 @@
 use fnv::{FnvHashMap};
+use smol_str::SmolStr;
 
 use crate::behavior::Value;
+use crate::util::UnwrapOrEmptyIter;
 
 use super::{World};
 use super::entities::{Entity};
@@ -10,6 +12,8 @@ use super::entities::{Entity};
 pub(super) struct WorldAgents {
     agent_locations: FnvHashMap<Entity, Entity>,
     agent_position: FnvHashMap<Entity, Value>,
+    agent_roots: FnvHashMap<Entity, SmolStr>,
+    agent_memory: FnvHashMap<Entity, FnvHashMap<Value, Value>>,
 }
 
 impl World {
@@ -20,10 +24,50 @@ impl World {
         agent
     }
 
+    /// Creates an agent with a kind, used for root selection.
+    pub fn create_agent_with_kind(&mut self, kind: Value, location: Entity) -> Entity {
+        let agent = self.create_agent(location);
+        self.set_layout_kind(agent, kind);
+        agent
+    }
+
+    /// Removes an agent along with all of its state.
+    pub fn remove_agent(&mut self, agent: Entity) {
+        assert!(self.is_agent(agent));
+        self.agents.agent_locations.remove(&agent);
+        self.agents.agent_position.remove(&agent);
+        self.agents.agent_roots.remove(&agent);
+        self.agents.agent_memory.remove(&agent);
+        self.remove_agent_activities(agent);
+        self.remove_agent_rejections(agent);
+        self.clear_layout_kind(agent);
+        self.despawn(agent);
+    }
+
     pub fn agents(&self) -> impl Iterator<Item = Entity> + '_ {
         self.agents.agent_locations.keys().copied()
     }
 
+    pub fn agents_in_area(&self, area: Entity) -> impl Iterator<Item = Entity> + '_ {
+        self.agents.agent_locations.iter().filter_map(move |(agent, location)| {
+            if *location == area {
+                Some(*agent)
+            } else {
+                None
+            }
+        })
+    }
+
+    pub fn agents_in_space(&self, space: Entity) -> impl Iterator<Item = Entity> + '_ {
+        self.agents.agent_locations.iter().filter_map(move |(agent, location)| {
+            if self.object_space(*location) == Some(space) {
+                Some(*agent)
+            } else {
+                None
+            }
+        })
+    }
+
     pub fn is_agent(&self, entity: Entity) -> bool {
         self.agents.agent_locations.contains_key(&entity)
     }
@@ -50,4 +94,41 @@ impl World {
     pub fn agent_position(&self, agent: Entity) -> Option<&Value> {
         self.agents.agent_position.get(&agent)
     }
+
+    /// Override the behavior root action for an agent.
+    pub fn set_agent_root<T>(&mut self, agent: Entity, root: T)
+    where
+        T: Into<SmolStr>,
+    {
+        assert!(self.is_agent(agent));
+        self.agents.agent_roots.insert(agent, root.into());
+    }
+
+    pub fn clear_agent_root(&mut self, agent: Entity) {
+        assert!(self.is_agent(agent));
+        self.agents.agent_roots.remove(&agent);
+    }
+
+    pub fn agent_root(&self, agent: Entity) -> Option<&SmolStr> {
+        self.agents.agent_roots.get(&agent)
+    }
+
+    /// Remember a value in the agent's blackboard memory.
+    pub fn set_agent_memory(&mut self, agent: Entity, key: Value, value: Value) {
+        assert!(self.is_agent(agent));
+        self.agents.agent_memory.entry(agent).or_default().insert(key, value);
+    }
+
+    pub fn clear_agent_memory(&mut self, agent: Entity, key: &Value) -> Option<Value> {
+        assert!(self.is_agent(agent));
+        self.agents.agent_memory.get_mut(&agent)?.remove(key)
+    }
+
+    pub fn agent_memory(&self, agent: Entity, key: &Value) -> Option<&Value> {
+        self.agents.agent_memory.get(&agent)?.get(key)
+    }
+
+    pub fn agent_memories(&self, agent: Entity) -> impl Iterator<Item = (&Value, &Value)> + '_ {
+        self.agents.agent_memory.get(&agent).unwrap_or_empty_iter()
+    }
 }
\ No newline at end of file
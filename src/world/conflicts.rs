@@ -0,0 +1,226 @@
+use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher};
+
+use fnv::FnvHasher;
+
+use crate::behavior::{Effect, Value};
+
+use super::World;
+use super::entities::{Entity, EntitySet};
+use super::step::Decisions;
+
+
+/// Decides which agent gets to proceed when multiple agents claim the same entity.
+pub trait ConflictResolver {
+    /// The entity an effect requires exclusive access to, if any.
+    fn claim(&self, world: &World, agent: Entity, effect: &Effect) -> Option<Entity> {
+        let _ = (world, agent);
+        effect.claimed_entity()
+    }
+
+    /// Picks the winning agent from the contenders for an entity.
+    fn resolve(&mut self, world: &World, entity: Entity, contenders: &[Entity]) -> Entity;
+}
+
+/// Resolves conflicts by an integer priority attribute, breaking ties by seeded randomness.
+///
+/// Tie breaking also depends on the world tick, so ties between the same agents are decided
+/// differently across steps.
+#[derive(Debug, Clone, Default)]
+pub struct PriorityResolver {
+    priority_attribute: Option<Value>,
+    state: u64,
+}
+
+impl PriorityResolver {
+    pub fn new(seed: u64) -> Self {
+        Self { priority_attribute: None, state: seed }
+    }
+
+    /// Use the global attribute `attr` on agents as priority, higher values winning.
+    pub fn with_priority_attribute(mut self, attr: Value) -> Self {
+        self.priority_attribute = Some(attr);
+        self
+    }
+
+    fn priority(&self, world: &World, agent: Entity) -> i64 {
+        let Some(attr) = &self.priority_attribute else {
+            return 0;
+        };
+        match world.global_attribute_value(agent, attr) {
+            Ok(Some(Value::Int(priority))) => *priority,
+            _ => 0,
+        }
+    }
+
+    fn tie_breaker(&self, world: &World, entity: Entity, agent: Entity) -> u64 {
+        let mut hasher = FnvHasher::with_key(self.state);
+        (world.tick(), entity, agent).hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+impl ConflictResolver for PriorityResolver {
+    fn resolve(&mut self, world: &World, entity: Entity, contenders: &[Entity]) -> Entity {
+        let winner = contenders.iter().copied()
+            .max_by_key(|agent| {
+                (self.priority(world, *agent), self.tie_breaker(world, entity, *agent))
+            })
+            .expect("conflict contenders");
+        self.state = self.tie_breaker(world, entity, winner);
+        winner
+    }
+}
+
+/// An agent that lost the claim on an entity to another agent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct LostClaim {
+    pub(super) agent: Entity,
+    pub(super) entity: Entity,
+    pub(super) winner: Entity,
+}
+
+impl World {
+    /// Picks the agents allowed to proceed and the claims lost by all other contenders.
+    ///
+    /// A winner keeps every entity it claims, since its effects are applied as a whole, so
+    /// all other contenders for those entities lose to it right away. This guarantees that
+    /// an agent reported as a winner is never rejected itself.
+    pub(super) fn resolve_conflicts<R>(
+        &self,
+        decisions: &Decisions,
+        resolver: &mut R,
+    ) -> Vec<LostClaim>
+    where
+        R: ConflictResolver + ?Sized,
+    {
+        let mut claims = BTreeMap::<Entity, Vec<Entity>>::new();
+        let mut agent_claims = BTreeMap::<Entity, Vec<Entity>>::new();
+        for (agent, effects) in decisions.iter() {
+            for effect in effects {
+                if let Some(entity) = resolver.claim(self, agent, effect) {
+                    let contenders = claims.entry(entity).or_default();
+                    if !contenders.contains(&agent) {
+                        contenders.push(agent);
+                        agent_claims.entry(agent).or_default().push(entity);
+                    }
+                }
+            }
+        }
+        let mut winners = EntitySet::default();
+        let mut eliminated = EntitySet::default();
+        let mut lost = Vec::new();
+        for (entity, contenders) in &claims {
+            if contenders.iter().any(|agent| winners.contains(agent)) {
+                continue;
+            }
+            let contenders = contenders.iter().copied()
+                .filter(|agent| !eliminated.contains(agent))
+                .collect::<Vec<_>>();
+            if contenders.len() < 2 {
+                continue;
+            }
+            let winner = resolver.resolve(self, *entity, &contenders);
+            winners.insert(winner);
+            for claimed in &agent_claims[&winner] {
+                for &agent in &claims[claimed] {
+                    if agent != winner && eliminated.insert(agent) {
+                        lost.push(LostClaim { agent, entity: *claimed, winner });
+                    }
+                }
+            }
+        }
+        lost
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fnv::FnvHashMap;
+
+    use crate::behavior::Effect;
+    use crate::testing::symbol;
+    use crate::world::World;
+    use crate::world::entities::{Entity, EntitySet};
+    use crate::world::step::{Decisions, Rejection};
+
+    use super::ConflictResolver;
+
+
+    /// Picks a fixed agent per entity, falling back to the first contender.
+    struct FixedResolver(FnvHashMap<Entity, Entity>);
+
+    impl ConflictResolver for FixedResolver {
+        fn resolve(&mut self, _: &World, entity: Entity, contenders: &[Entity]) -> Entity {
+            self.0.get(&entity).copied()
+                .filter(|agent| contenders.contains(agent))
+                .unwrap_or(contenders[0])
+        }
+    }
+
+    fn tag(entity: Entity) -> Effect {
+        Effect::SetGlobalTag { entity, tag: symbol("taken") }
+    }
+
+    #[test]
+    fn winners_keep_all_their_claims() {
+        let mut world = World::default();
+        let space = world.create_space(symbol("room"));
+        let area = world.create_object(symbol("floor"), space);
+        let first = world.create_object(symbol("item"), area);
+        let second = world.create_object(symbol("item"), area);
+        let both = world.create_agent(area);
+        let only_first = world.create_agent(area);
+        let only_second = world.create_agent(area);
+        let decisions = Decisions::from_iter([
+            (both, vec![tag(first), tag(second)]),
+            (only_first, vec![tag(first)]),
+            (only_second, vec![tag(second)]),
+        ]);
+        let mut resolver = FixedResolver(FnvHashMap::from_iter([
+            (first, both),
+            (second, only_second),
+        ]));
+        let report = world.apply_decisions_with(decisions, &mut resolver);
+
+        let taken = symbol("taken");
+        assert_eq!(world.contains_global_tag(first, &taken), Ok(true));
+        assert_eq!(world.contains_global_tag(second, &taken), Ok(true));
+        assert_eq!(report.rejected.len(), 2);
+        for rejected in &report.rejected {
+            assert_ne!(rejected.agent, both);
+            assert!(matches!(
+                rejected.rejection,
+                Rejection::Conflict { winner, .. } if winner == both,
+            ));
+        }
+        assert_eq!(world.lost_claims(only_first).collect::<Vec<_>>(), [first]);
+        assert_eq!(world.lost_claims(only_second).collect::<Vec<_>>(), [second]);
+    }
+
+    #[test]
+    fn ties_are_decided_differently_across_steps() {
+        let mut world = World::default();
+        let space = world.create_space(symbol("room"));
+        let area = world.create_object(symbol("floor"), space);
+        let item = world.create_object(symbol("item"), area);
+        let first = world.create_agent(area);
+        let second = world.create_agent(area);
+        let mut winners = EntitySet::default();
+        for _ in 0..32 {
+            let decisions = Decisions::from_iter([
+                (first, vec![tag(item)]),
+                (second, vec![tag(item)]),
+            ]);
+            let report = world.apply_decisions(decisions);
+            let [rejected] = report.rejected.as_slice() else {
+                panic!("expected a single rejection");
+            };
+            let Rejection::Conflict { winner, .. } = rejected.rejection else {
+                panic!("expected a conflict");
+            };
+            winners.insert(winner);
+        }
+        assert_eq!(winners.len(), 2);
+    }
+}
@@ -0,0 +1,425 @@
+use crate::behavior::{Effect, Value};
+
+use super::{World, InvalidEntity};
+use super::activities::{Activity, FinishedActivity, ActivityOutcome};
+use super::entities::Entity;
+use super::layout::MoveObjectError;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, thiserror::Error)]
+pub enum EffectError {
+    #[error("Effect source is not an agent")]
+    InvalidAgent,
+    #[error("Effect target is not a valid entity")]
+    InvalidEntity,
+    #[error("Movement target is not an area")]
+    InvalidArea,
+    #[error("Movement target is not in the agent's current space")]
+    ForeignSpace,
+    #[error("Entity is not a portal object")]
+    InvalidPortal,
+    #[error("Agent is not located at the portal object")]
+    PortalOutOfReach,
+    #[error("Portal is closed")]
+    PortalClosed,
+    #[error("Portal cannot be entered from this side")]
+    PortalExitOnly,
+    #[error("Moved object or its new parent is not in the agent's current space")]
+    ObjectOutOfReach,
+    #[error(transparent)]
+    MoveObject(#[from] MoveObjectError),
+    #[error("Agent is already busy with an activity")]
+    ActivityInProgress,
+    #[error("Agent is not busy with an activity")]
+    NoActivity,
+    #[error("Activity duration is not a non-negative integer")]
+    InvalidDuration,
+}
+
+impl From<InvalidEntity> for EffectError {
+    fn from(_: InvalidEntity) -> Self {
+        Self::InvalidEntity
+    }
+}
+
+pub type EffectResult<T = ()> = Result<T, EffectError>;
+
+/// Failure of a single effect in a sequence of effects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct EffectFailure {
+    pub index: usize,
+    pub error: EffectError,
+}
+
+/// Information required to revert an applied effect.
+#[derive(Debug, Clone)]
+enum Undo {
+    AgentLocation { agent: Entity, location: Entity },
+    ObjectParent { object: Entity, parent: Entity },
+    GlobalAttribute { entity: Entity, attr: Value, value: Option<Value> },
+    GlobalTag { entity: Entity, tag: Value, present: bool },
+    AgentAttribute { agent: Entity, entity: Entity, attr: Value, value: Option<Value> },
+    AgentTag { agent: Entity, entity: Entity, tag: Value, present: bool },
+    Memory { agent: Entity, key: Value, value: Option<Value> },
+    Activity {
+        agent: Entity,
+        active: Option<Activity>,
+        finished: Option<FinishedActivity>,
+    },
+    Sequence(Vec<Undo>),
+}
+
+impl World {
+    pub fn apply_effect(&mut self, agent: Entity, effect: &Effect) -> EffectResult {
+        self.apply_effect_with_undo(agent, effect)?;
+        Ok(())
+    }
+
+    /// Applies a sequence of effects for an agent as a single transaction.
+    ///
+    /// If any of the effects fails, all previously applied effects are reverted and the
+    /// world is left unchanged.
+    pub fn apply_effects(
+        &mut self,
+        agent: Entity,
+        effects: &[Effect],
+    ) -> Result<(), EffectFailure> {
+        self.apply_effects_with_undo(agent, effects)?;
+        Ok(())
+    }
+
+    fn apply_effects_with_undo(
+        &mut self,
+        agent: Entity,
+        effects: &[Effect],
+    ) -> Result<Vec<Undo>, EffectFailure> {
+        let mut undos = Vec::with_capacity(effects.len());
+        for (index, effect) in effects.iter().enumerate() {
+            match self.apply_effect_with_undo(agent, effect) {
+                Ok(undo) => undos.push(undo),
+                Err(error) => {
+                    self.revert_effect(Undo::Sequence(undos));
+                    return Err(EffectFailure { index, error });
+                },
+            }
+        }
+        Ok(undos)
+    }
+
+    fn apply_effect_with_undo(&mut self, agent: Entity, effect: &Effect) -> EffectResult<Undo> {
+        let location = self.agent_location(agent).ok_or(EffectError::InvalidAgent)?;
+        match effect {
+            Effect::MoveToArea { area } => {
+                if !self.is_area(*area) {
+                    return Err(EffectError::InvalidArea);
+                }
+                if self.object_space(*area) != self.object_space(location) {
+                    return Err(EffectError::ForeignSpace);
+                }
+                self.set_agent_location(agent, *area);
+                Ok(Undo::AgentLocation { agent, location })
+            },
+            Effect::TraversePortal { portal_object } => {
+                let target = self.object_portal_target(*portal_object)
+                    .ok_or(EffectError::InvalidPortal)?;
+                if location != *portal_object {
+                    return Err(EffectError::PortalOutOfReach);
+                }
+                if !self.is_portal_entrance(*portal_object) {
+                    return Err(EffectError::PortalExitOnly);
+                }
+                if !self.can_traverse(*portal_object) {
+                    return Err(EffectError::PortalClosed);
+                }
+                self.set_agent_location(agent, target);
+                Ok(Undo::AgentLocation { agent, location })
+            },
+            Effect::MoveObject { object, parent } => {
+                let previous = self.object_parent(*object).ok_or(MoveObjectError::InvalidObject)?;
+                let space = self.object_space(location);
+                let in_reach = |entity| {
+                    self.object_space(entity).map_or(true, |other| Some(other) == space)
+                };
+                if !in_reach(*object) || !in_reach(*parent) {
+                    return Err(EffectError::ObjectOutOfReach);
+                }
+                self.move_object(*object, *parent)?;
+                Ok(Undo::ObjectParent { object: *object, parent: previous })
+            },
+            Effect::SetGlobalAttribute { entity, attr, value } => {
+                let previous = self.global_attribute_value(*entity, attr)?.cloned();
+                self.set_global_attribute_value(*entity, attr.clone(), value.clone())?;
+                Ok(Undo::GlobalAttribute { entity: *entity, attr: attr.clone(), value: previous })
+            },
+            Effect::ClearGlobalAttribute { entity, attr } => {
+                let previous = self.clear_global_attribute_value(*entity, attr)?;
+                Ok(Undo::GlobalAttribute { entity: *entity, attr: attr.clone(), value: previous })
+            },
+            Effect::SetGlobalTag { entity, tag } => {
+                let present = self.contains_global_tag(*entity, tag)?;
+                self.set_global_tag(*entity, tag.clone())?;
+                Ok(Undo::GlobalTag { entity: *entity, tag: tag.clone(), present })
+            },
+            Effect::ClearGlobalTag { entity, tag } => {
+                let present = self.contains_global_tag(*entity, tag)?;
+                self.clear_global_tag(*entity, tag)?;
+                Ok(Undo::GlobalTag { entity: *entity, tag: tag.clone(), present })
+            },
+            Effect::SetAgentAttribute { entity, attr, value } => {
+                let previous = self.agent_attribute_value(agent, *entity, attr)?.cloned();
+                self.set_agent_attribute_value(agent, *entity, attr.clone(), value.clone())?;
+                Ok(Undo::AgentAttribute {
+                    agent,
+                    entity: *entity,
+                    attr: attr.clone(),
+                    value: previous,
+                })
+            },
+            Effect::ClearAgentAttribute { entity, attr } => {
+                let previous = self.clear_agent_attribute_value(agent, *entity, attr)?;
+                Ok(Undo::AgentAttribute {
+                    agent,
+                    entity: *entity,
+                    attr: attr.clone(),
+                    value: previous,
+                })
+            },
+            Effect::SetAgentTag { entity, tag } => {
+                let present = self.has_agent_tag(agent, *entity, tag)?;
+                self.set_agent_tag(agent, *entity, tag.clone())?;
+                Ok(Undo::AgentTag { agent, entity: *entity, tag: tag.clone(), present })
+            },
+            Effect::ClearAgentTag { entity, tag } => {
+                let present = self.has_agent_tag(agent, *entity, tag)?;
+                self.clear_agent_tag(agent, *entity, tag)?;
+                Ok(Undo::AgentTag { agent, entity: *entity, tag: tag.clone(), present })
+            },
+            Effect::Remember { key, value } => {
+                let previous = self.agent_memory(agent, key).cloned();
+                self.set_agent_memory(agent, key.clone(), value.clone());
+                Ok(Undo::Memory { agent, key: key.clone(), value: previous })
+            },
+            Effect::Forget { key } => {
+                let previous = self.clear_agent_memory(agent, key);
+                Ok(Undo::Memory { agent, key: key.clone(), value: previous })
+            },
+            Effect::StartActivity { name, duration } => {
+                let duration = match duration {
+                    Value::Int(duration) => u64::try_from(*duration)
+                        .map_err(|_| EffectError::InvalidDuration)?,
+                    _ => return Err(EffectError::InvalidDuration),
+                };
+                if self.agent_activity(agent).is_some() {
+                    return Err(EffectError::ActivityInProgress);
+                }
+                let activity = Activity {
+                    name: name.clone(),
+                    started: self.tick(),
+                    duration,
+                };
+                let finished = self.agent_finished_activity(agent).cloned();
+                let (active, finished) =
+                    self.replace_agent_activity(agent, Some(activity), finished);
+                Ok(Undo::Activity { agent, active, finished })
+            },
+            Effect::InterruptActivity => {
+                let activity = self.agent_activity(agent).cloned()
+                    .ok_or(EffectError::NoActivity)?;
+                let (active, finished) = self.replace_agent_activity(agent, None, Some(
+                    FinishedActivity {
+                        activity,
+                        outcome: ActivityOutcome::Interrupted,
+                        tick: self.tick(),
+                    },
+                ));
+                Ok(Undo::Activity { agent, active, finished })
+            },
+            Effect::Score { .. } => Ok(Undo::Sequence(Vec::new())),
+            Effect::Custom(custom) => {
+                let effects = custom.payload().expand(self, agent)?;
+                self.apply_effects_with_undo(agent, &effects)
+                    .map(Undo::Sequence)
+                    .map_err(|failure| failure.error)
+            },
+        }
+    }
+
+    fn revert_effect(&mut self, undo: Undo) {
+        let result = match undo {
+            Undo::AgentLocation { agent, location } => {
+                self.set_agent_location(agent, location);
+                Ok(())
+            },
+            Undo::ObjectParent { object, parent } => {
+                self.move_object(object, parent).expect("reverted move is valid");
+                Ok(())
+            },
+            Undo::GlobalAttribute { entity, attr, value: Some(value) } => {
+                self.set_global_attribute_value(entity, attr, value)
+            },
+            Undo::GlobalAttribute { entity, attr, value: None } => {
+                self.clear_global_attribute_value(entity, &attr).map(|_| ())
+            },
+            Undo::GlobalTag { entity, tag, present: true } => {
+                self.set_global_tag(entity, tag)
+            },
+            Undo::GlobalTag { entity, tag, present: false } => {
+                self.clear_global_tag(entity, &tag)
+            },
+            Undo::AgentAttribute { agent, entity, attr, value: Some(value) } => {
+                self.set_agent_attribute_value(agent, entity, attr, value)
+            },
+            Undo::AgentAttribute { agent, entity, attr, value: None } => {
+                self.clear_agent_attribute_value(agent, entity, &attr).map(|_| ())
+            },
+            Undo::AgentTag { agent, entity, tag, present: true } => {
+                self.set_agent_tag(agent, entity, tag)
+            },
+            Undo::AgentTag { agent, entity, tag, present: false } => {
+                self.clear_agent_tag(agent, entity, &tag)
+            },
+            Undo::Memory { agent, key, value: Some(value) } => {
+                self.set_agent_memory(agent, key, value);
+                Ok(())
+            },
+            Undo::Memory { agent, key, value: None } => {
+                self.clear_agent_memory(agent, &key);
+                Ok(())
+            },
+            Undo::Activity { agent, active, finished } => {
+                self.replace_agent_activity(agent, active, finished);
+                Ok(())
+            },
+            Undo::Sequence(undos) => {
+                for undo in undos.into_iter().rev() {
+                    self.revert_effect(undo);
+                }
+                Ok(())
+            },
+        };
+        result.expect("reverted effect applies to valid entities");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use float_ord::FloatOrd;
+
+    use crate::behavior::{Effect, Value};
+    use crate::testing::symbol;
+    use crate::world::World;
+
+    use super::{EffectError, EffectFailure};
+
+
+    #[test]
+    fn move_object_requires_reach() {
+        let mut world = World::default();
+        let hall = world.create_space(symbol("room"));
+        let yard = world.create_space(symbol("room"));
+        let hearth = world.create_object(symbol("floor"), hall);
+        let chest = world.create_object(symbol("chest"), hearth);
+        let lawn = world.create_object(symbol("floor"), yard);
+        let coin = world.create_object(symbol("coin"), lawn);
+        let agent = world.create_agent(hearth);
+
+        let far_object = Effect::MoveObject { object: coin, parent: chest };
+        let far_parent = Effect::MoveObject { object: chest, parent: lawn };
+        for effect in [far_object, far_parent] {
+            assert_eq!(world.apply_effect(agent, &effect), Err(EffectError::ObjectOutOfReach));
+        }
+        assert_eq!(world.object_parent(coin), Some(lawn));
+        assert_eq!(world.object_parent(chest), Some(hearth));
+
+        let corner = world.create_object(symbol("floor"), hall);
+        world.apply_effect(agent, &Effect::MoveObject { object: chest, parent: corner }).unwrap();
+        assert_eq!(world.object_parent(chest), Some(corner));
+    }
+
+    #[test]
+    fn failed_transactions_leave_the_world_unchanged() {
+        let mut world = World::default();
+        let hall = world.create_space(symbol("room"));
+        let hearth = world.create_object(symbol("floor"), hall);
+        let removed = world.create_object(symbol("floor"), hall);
+        world.remove_object(removed);
+        let agent = world.create_agent(hearth);
+        let calm = Effect::Remember { key: symbol("mood"), value: symbol("calm") };
+        world.apply_effect(agent, &calm).unwrap();
+
+        let effects = [
+            Effect::SetGlobalTag { entity: hearth, tag: symbol("warm") },
+            Effect::SetAgentAttribute {
+                entity: hearth,
+                attr: symbol("visits"),
+                value: Value::Int(1),
+            },
+            Effect::Remember { key: symbol("mood"), value: symbol("restless") },
+            Effect::StartActivity { name: symbol("resting"), duration: Value::Int(2) },
+            Effect::SetGlobalTag { entity: removed, tag: symbol("warm") },
+        ];
+        let failure = world.apply_effects(agent, &effects).unwrap_err();
+        assert_eq!(failure, EffectFailure { index: 4, error: EffectError::InvalidEntity });
+        assert_eq!(world.contains_global_tag(hearth, &symbol("warm")), Ok(false));
+        assert_eq!(world.agent_attribute_value(agent, hearth, &symbol("visits")), Ok(None));
+        assert_eq!(world.agent_memory(agent, &symbol("mood")), Some(&symbol("calm")));
+        assert!(world.agent_activity(agent).is_none());
+    }
+
+    #[test]
+    fn portals_can_only_be_entered_when_open_and_from_an_entrance() {
+        let mut world = World::default();
+        let hall = world.create_space(symbol("room"));
+        let yard = world.create_space(symbol("room"));
+        let chute = world.create_one_way_portal(symbol("chute"), (hall, yard));
+        let gate = world.create_portal(symbol("gate"), (hall, yard));
+        let object = |world: &World, portal, space| {
+            world.portal_objects(portal)
+                .find(|object| world.object_space(*object) == Some(space))
+                .unwrap()
+        };
+        let chute_hall = object(&world, chute, hall);
+        let chute_yard = object(&world, chute, yard);
+        let gate_hall = object(&world, gate, hall);
+        let gate_yard = object(&world, gate, yard);
+
+        let agent = world.create_agent(chute_yard);
+        let traverse = |portal_object| Effect::TraversePortal { portal_object };
+        assert_eq!(
+            world.apply_effect(agent, &traverse(chute_yard)),
+            Err(EffectError::PortalExitOnly),
+        );
+        assert_eq!(world.agent_location(agent), Some(chute_yard));
+
+        world.close_portal(gate);
+        world.set_agent_location(agent, gate_yard);
+        assert_eq!(world.apply_effect(agent, &traverse(gate_yard)), Err(EffectError::PortalClosed));
+        assert_eq!(world.agent_location(agent), Some(gate_yard));
+
+        world.open_portal(gate);
+        world.apply_effect(agent, &traverse(gate_yard)).unwrap();
+        assert_eq!(world.agent_location(agent), Some(gate_hall));
+        world.set_agent_location(agent, chute_hall);
+        world.apply_effect(agent, &traverse(chute_hall)).unwrap();
+        assert_eq!(world.agent_location(agent), Some(chute_yard));
+    }
+
+    #[test]
+    fn activity_durations_must_be_non_negative_integers() {
+        let mut world = World::default();
+        let hall = world.create_space(symbol("room"));
+        let hearth = world.create_object(symbol("floor"), hall);
+        let agent = world.create_agent(hearth);
+        let start = |duration| Effect::StartActivity { name: symbol("resting"), duration };
+
+        for duration in [Value::Int(-1), Value::Float(FloatOrd(2.5)), symbol("long")] {
+            assert_eq!(
+                world.apply_effect(agent, &start(duration)),
+                Err(EffectError::InvalidDuration),
+            );
+            assert!(world.agent_activity(agent).is_none());
+        }
+        world.apply_effect(agent, &start(Value::Int(0))).unwrap();
+        assert_eq!(world.agent_activity(agent).map(|activity| activity.duration), Some(0));
+    }
+}
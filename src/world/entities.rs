@@ -47,6 +47,7 @@ impl World {
         self.entities.meta.remove(&entity);
         for meta in self.entities.meta.values_mut() {
             meta.agent_attributes.remove(&entity);
+            meta.agent_tags.remove(&entity);
         }
     }
 
@@ -132,6 +133,16 @@ impl World {
     pub fn contains_global_tag(&self, entity: Entity, tag: &Value) -> EntityResult<bool> {
         Ok(self.meta(entity)?.global_tags.contains(tag))
     }
+
+    pub fn global_tagged<'a>(&'a self, tag: &'a Value) -> impl Iterator<Item = Entity> + 'a {
+        self.entities.meta.iter().filter_map(move |(entity, meta)| {
+            if meta.global_tags.contains(tag) {
+                Some(*entity)
+            } else {
+                None
+            }
+        })
+    }
 }
 
 /// Agent local entity attributes.
@@ -218,4 +229,18 @@ impl World {
     ) -> EntityResult<bool> {
         Ok(self.meta(entity)?.agent_tags.get(&agent).map_or(false, |tags| tags.contains(tag)))
     }
+
+    pub fn agent_tagged<'a>(
+        &'a self,
+        agent: Entity,
+        tag: &'a Value,
+    ) -> impl Iterator<Item = Entity> + 'a {
+        self.entities.meta.iter().filter_map(move |(entity, meta)| {
+            if meta.agent_tags.get(&agent).map_or(false, |tags| tags.contains(tag)) {
+                Some(*entity)
+            } else {
+                None
+            }
+        })
+    }
 }
\ No newline at end of file
@@ -1,7 +1,8 @@
-use std::collections::VecDeque;
-use std::sync::Arc;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, VecDeque};
+use std::num::NonZeroUsize;
 
-use coupled::Pair;
+use float_ord::FloatOrd;
 use fnv::FnvHashMap;
 use smallvec::SmallVec;
 
@@ -9,6 +10,7 @@ use crate::behavior::Value;
 
 use super::World;
 use super::entities::{EntitySet, Entity};
+use super::routes::Route;
 
 
 type LocalBuffer<T> = SmallVec<[T; 64]>;
@@ -20,14 +22,99 @@ pub(super) struct WorldLayout {
     kinds: FnvHashMap<Entity, Value>,
     portals: EntitySet,
     portal_objects: FnvHashMap<Entity, PortalTarget>,
-    paths: FnvHashMap<(Entity, Entity), Vec<Value>>,
-    space_distances: FnvHashMap<Pair<Entity>, usize>,
+    closed_portals: EntitySet,
+    paths: FnvHashMap<(Entity, Entity), Vec<Route>>,
+    path_limits: PathLimits,
+    travel_costs: FnvHashMap<Entity, f64>,
+    kind_travel_costs: FnvHashMap<Value, f64>,
+    space_distances: FnvHashMap<(Entity, Entity), FloatOrd<f64>>,
+    deferred_spaces: Option<EntitySet>,
+}
+
+/// Travel cost used when neither the entity nor its kind specify one.
+pub const DEFAULT_TRAVEL_COST: f64 = 1.0;
+
+/// Bounds on the paths precalculated between areas.
+///
+/// Paths are enumerated in order of their total travel cost, so limiting the number of paths
+/// per pair keeps the cheapest ones. Space distances are not affected by these limits.
+///
+/// By default only [`DEFAULT_MAX_PATHS_PER_PAIR`] paths are kept between two areas, which
+/// bounds memory use by the square of the number of areas in a component. The bound also
+/// holds while paths are calculated, since candidates for a full pair are dropped early.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PathLimits {
+    /// Maximum number of areas in a path.
+    pub max_length: Option<usize>,
+    /// Maximum number of paths kept between two areas. `None` keeps every simple path,
+    /// which grows combinatorially with the number of portals.
+    pub max_per_pair: Option<NonZeroUsize>,
+}
+
+pub const DEFAULT_MAX_PATHS_PER_PAIR: usize = 4;
+
+impl Default for PathLimits {
+    fn default() -> Self {
+        Self {
+            max_length: None,
+            max_per_pair: NonZeroUsize::new(DEFAULT_MAX_PATHS_PER_PAIR),
+        }
+    }
+}
+
+/// Reasons an object cannot be moved to a new parent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, thiserror::Error)]
+pub enum MoveObjectError {
+    #[error("Moved entity is not an object")]
+    InvalidObject,
+    #[error("New parent is neither a space nor an object")]
+    InvalidParent,
+    #[error("New parent is the object itself or one of its descendants")]
+    Cycle,
+    #[error("Portal objects cannot be moved")]
+    PortalObject,
+    #[error("Area with agents located at it cannot stop being an area")]
+    OccupiedArea,
 }
 
 #[derive(Debug, Clone)]
 struct PortalTarget {
     portal: Entity,
     target_object: Entity,
+    entrance: bool,
+}
+
+/// Partial path waiting to be extended, ordered cheapest and then earliest found first.
+#[derive(Debug)]
+struct PendingPath {
+    route: Route,
+    order: usize,
+}
+
+impl PendingPath {
+    fn key(&self) -> Reverse<(FloatOrd<f64>, usize)> {
+        Reverse((FloatOrd(self.route.cost), self.order))
+    }
+}
+
+impl PartialEq for PendingPath {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for PendingPath {}
+
+impl PartialOrd for PendingPath {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for PendingPath {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
 }
 
 impl World {
@@ -35,14 +122,107 @@ impl World {
         self.layout.kinds.get(&entity)
     }
 
+    pub(super) fn set_layout_kind(&mut self, entity: Entity, kind: Value) {
+        self.layout.kinds.insert(entity, kind);
+    }
+
+    pub(super) fn clear_layout_kind(&mut self, entity: Entity) {
+        self.layout.kinds.remove(&entity);
+    }
+
     pub fn create_space(&mut self, kind: Value) -> Entity {
         let entity = self.spawn();
         self.layout.spaces.insert(entity);
         self.layout.kinds.insert(entity, kind);
-        self.recalculate();
         entity
     }
 
+    pub fn path_limits(&self) -> PathLimits {
+        self.layout.path_limits
+    }
+
+    /// Precalculated routes between two different areas, cheapest first.
+    pub fn routes(&self, from: Entity, to: Entity) -> &[Route] {
+        self.layout.paths.get(&(from, to)).map_or(&[], |routes| routes.as_slice())
+    }
+
+    /// Runs `f` with path and distance recalculation deferred until it returns.
+    ///
+    /// Every layout change normally recalculates its whole connected component. Building or
+    /// changing many areas and portals inside `f` instead recalculates each affected
+    /// component once. Routes and space distances are outdated while `f` runs.
+    pub fn defer_layout_updates<F, T>(&mut self, f: F) -> T
+    where
+        F: FnOnce(&mut Self) -> T,
+    {
+        if self.layout.deferred_spaces.is_some() {
+            return f(self);
+        }
+        self.layout.deferred_spaces = Some(EntitySet::default());
+        let result = f(self);
+        let deferred = self.layout.deferred_spaces.take().unwrap();
+        let mut spaces = EntitySet::default();
+        for space in deferred {
+            if self.is_space(space) && !spaces.contains(&space) {
+                spaces.extend(self.connected_spaces(space));
+            }
+        }
+        self.recalculate_spaces(&spaces);
+        result
+    }
+
+    pub fn set_path_limits(&mut self, limits: PathLimits) {
+        self.layout.path_limits = limits;
+        let spaces = self.layout.spaces.clone();
+        self.recalculate_spaces(&spaces);
+    }
+
+    /// Set the cost of crossing a portal or of moving between two areas of a space.
+    pub fn set_travel_cost(&mut self, entity: Entity, cost: f64) {
+        assert!(self.is_portal(entity) || self.is_space(entity));
+        assert!(cost >= 0.0);
+        self.layout.travel_costs.insert(entity, cost);
+        self.recalculate_portal_or_space(entity);
+    }
+
+    pub fn clear_travel_cost(&mut self, entity: Entity) {
+        assert!(self.is_portal(entity) || self.is_space(entity));
+        self.layout.travel_costs.remove(&entity);
+        self.recalculate_portal_or_space(entity);
+    }
+
+    /// Set the travel cost for portals and spaces of a kind without their own cost.
+    pub fn set_kind_travel_cost(&mut self, kind: Value, cost: f64) {
+        assert!(cost >= 0.0);
+        self.layout.kind_travel_costs.insert(kind, cost);
+        let spaces = self.layout.spaces.clone();
+        self.recalculate_spaces(&spaces);
+    }
+
+    pub fn clear_kind_travel_cost(&mut self, kind: &Value) {
+        self.layout.kind_travel_costs.remove(kind);
+        let spaces = self.layout.spaces.clone();
+        self.recalculate_spaces(&spaces);
+    }
+
+    /// The cost of crossing a portal or of moving between two areas of a space.
+    pub fn travel_cost(&self, entity: Entity) -> f64 {
+        self.layout.travel_costs.get(&entity).copied()
+            .or_else(|| {
+                let kind = self.layout_kind(entity)?;
+                self.layout.kind_travel_costs.get(kind).copied()
+            })
+            .unwrap_or(DEFAULT_TRAVEL_COST)
+    }
+
+    fn recalculate_portal_or_space(&mut self, entity: Entity) {
+        let space = match self.portal_objects(entity).next() {
+            Some(object) => self.object_space(object).unwrap(),
+            None => entity,
+        };
+        self.recalculate_component(space);
+    }
+
     pub fn is_space(&self, entity: Entity) -> bool {
         self.layout.spaces.contains(&entity)
     }
@@ -51,9 +231,10 @@ impl World {
         self.layout.spaces.iter().copied()
     }
 
+    /// Other spaces reachable from `source`, ordered by total travel cost.
     pub fn spaces_by_distance(&self, source: Entity) -> impl Iterator<Item = Entity> + '_ {
         let mut spaces = self.spaces().filter_map(|space| {
-            let dist = self.layout.space_distances.get(&Pair::new(source, space)).copied()?;
+            let dist = self.layout.space_distances.get(&(source, space)).copied()?;
             Some((space, dist))
         }).collect::<LocalBuffer<_>>();
         spaces.sort_by_key(|(_, dist)| *dist);
@@ -61,11 +242,18 @@ impl World {
     }
 
     pub fn create_object(&mut self, kind: Value, parent: Entity) -> Entity {
+        let entity = self.insert_object(kind, parent);
+        if self.is_space(parent) {
+            self.recalculate_component(parent);
+        }
+        entity
+    }
+
+    fn insert_object(&mut self, kind: Value, parent: Entity) -> Entity {
         assert!(self.is_space(parent) || self.is_object(parent));
         let entity = self.spawn();
         self.layout.object_parents.insert(entity, parent);
         self.layout.kinds.insert(entity, kind);
-        self.recalculate();
         entity
     }
 
@@ -82,6 +270,11 @@ impl World {
         self.spaces().flat_map(|space| self.child_objects(space))
     }
 
+    pub fn space_areas(&self, space: Entity) -> impl Iterator<Item = Entity> + '_ {
+        assert!(self.is_space(space));
+        self.child_objects(space)
+    }
+
     pub fn child_objects(&self, parent: Entity) -> impl Iterator<Item = Entity> + '_ {
         self.layout.object_parents.iter().filter_map(move |(object, object_parent)| {
             if parent == *object_parent {
@@ -92,6 +285,144 @@ impl World {
         })
     }
 
+    /// Adds all objects below `parent` to `entities`.
+    pub(super) fn collect_descendants(&self, parent: Entity, entities: &mut EntitySet) {
+        for child in self.child_objects(parent) {
+            entities.insert(child);
+            self.collect_descendants(child, entities);
+        }
+    }
+
+    /// Removes an object with all of its descendants and the agents located in them.
+    ///
+    /// Removing a portal object removes the whole portal.
+    pub fn remove_object(&mut self, object: Entity) {
+        assert!(self.is_object(object));
+        if let Some(portal) = self.object_portal(object) {
+            self.remove_portal(portal);
+            return;
+        }
+        let space = self.object_space(object).unwrap();
+        let mut removed = EntitySet::from_iter([object]);
+        self.collect_descendants(object, &mut removed);
+        self.remove_layout_entities(removed, [space]);
+    }
+
+    /// Removes a space with all of its objects, the portals leading into it and the agents
+    /// located inside.
+    pub fn remove_space(&mut self, space: Entity) {
+        assert!(self.is_space(space));
+        let mut removed = EntitySet::from_iter([space]);
+        let mut affected = LocalBuffer::from_iter([space]);
+        self.collect_descendants(space, &mut removed);
+        let portals = self.space_portal_objects(space)
+            .filter_map(|object| self.object_portal(object))
+            .collect::<LocalBuffer<_>>();
+        for portal in portals {
+            self.collect_portal(portal, &mut removed, &mut affected);
+        }
+        self.remove_layout_entities(removed, affected);
+    }
+
+    /// Removes a portal with both of its objects and the agents located at them.
+    pub fn remove_portal(&mut self, portal: Entity) {
+        assert!(self.is_portal(portal));
+        let mut removed = EntitySet::default();
+        let mut affected = LocalBuffer::new();
+        self.collect_portal(portal, &mut removed, &mut affected);
+        self.remove_layout_entities(removed, affected);
+    }
+
+    fn collect_portal(
+        &self,
+        portal: Entity,
+        removed: &mut EntitySet,
+        affected: &mut LocalBuffer<Entity>,
+    ) {
+        removed.insert(portal);
+        for object in self.portal_objects(portal) {
+            removed.insert(object);
+            self.collect_descendants(object, removed);
+            affected.extend(self.object_space(object));
+        }
+    }
+
+    /// Removes layout entities and recalculates the components they were part of.
+    fn remove_layout_entities<I>(&mut self, removed: EntitySet, affected: I)
+    where
+        I: IntoIterator<Item = Entity>,
+    {
+        let mut spaces = EntitySet::default();
+        for space in affected {
+            if !spaces.contains(&space) {
+                spaces.extend(self.connected_spaces(space));
+            }
+        }
+        let agents = self.agents()
+            .filter(|agent| self.agent_location(*agent).map_or(false, |location| {
+                removed.contains(&location)
+            }))
+            .collect::<LocalBuffer<_>>();
+        for agent in agents {
+            self.remove_agent(agent);
+        }
+        for entity in &removed {
+            self.layout.spaces.remove(entity);
+            self.layout.object_parents.remove(entity);
+            self.layout.kinds.remove(entity);
+            self.layout.portals.remove(entity);
+            self.layout.portal_objects.remove(entity);
+            self.layout.closed_portals.remove(entity);
+            self.layout.travel_costs.remove(entity);
+            self.despawn(*entity);
+        }
+        self.layout.paths.retain(|(first, last), _| {
+            !removed.contains(first) && !removed.contains(last)
+        });
+        self.layout.space_distances.retain(|(source, target), _| {
+            !removed.contains(source) && !removed.contains(target)
+        });
+        spaces.retain(|space| !removed.contains(space));
+        self.recalculate_spaces(&spaces);
+    }
+
+    /// Moves an object with all of its descendants below a new parent.
+    ///
+    /// Paths are only recalculated when the object is an area before or after the move.
+    pub fn move_object(&mut self, object: Entity, parent: Entity) -> Result<(), MoveObjectError> {
+        let previous = self.object_parent(object).ok_or(MoveObjectError::InvalidObject)?;
+        if !self.is_space(parent) && !self.is_object(parent) {
+            return Err(MoveObjectError::InvalidParent);
+        }
+        if self.is_portal_object(object) {
+            return Err(MoveObjectError::PortalObject);
+        }
+        let mut ancestor = Some(parent);
+        while let Some(entity) = ancestor {
+            if entity == object {
+                return Err(MoveObjectError::Cycle);
+            }
+            ancestor = self.object_parent(entity);
+        }
+        let was_area = self.is_space(previous);
+        let is_area = self.is_space(parent);
+        if was_area && !is_area && self.agents_in_area(object).next().is_some() {
+            return Err(MoveObjectError::OccupiedArea);
+        }
+        let previous_space = self.object_space(object).unwrap();
+        self.layout.object_parents.insert(object, parent);
+        if was_area || is_area {
+            self.layout.paths.retain(|(first, _), _| *first != object);
+            let mut spaces = self.connected_spaces(previous_space);
+            let space = self.object_space(parent).unwrap();
+            if !spaces.contains(&space) {
+                spaces.extend(self.connected_spaces(space));
+            }
+            self.recalculate_spaces(&spaces);
+        }
+        Ok(())
+    }
+
     pub fn object_parent(&self, object: Entity) -> Option<Entity> {
         self.layout.object_parents.get(&object).copied()
     }
@@ -112,20 +443,71 @@ impl World {
         Some(entity)
     }
 
-    pub fn create_portal(&mut self, kind: Value, (sa, sb): (Entity, Entity)) -> Entity {
+    pub fn create_portal(&mut self, kind: Value, spaces: (Entity, Entity)) -> Entity {
+        self.insert_portal(kind, spaces, true)
+    }
+
+    /// Create a portal that can only be traversed from the first space to the second.
+    ///
+    /// Both spaces still receive a portal object, but the one in the second space is only an
+    /// exit.
+    pub fn create_one_way_portal(&mut self, kind: Value, spaces: (Entity, Entity)) -> Entity {
+        self.insert_portal(kind, spaces, false)
+    }
+
+    fn insert_portal(&mut self, kind: Value, (sa, sb): (Entity, Entity), two_way: bool) -> Entity {
         assert!(self.is_space(sa));
         assert!(self.is_space(sb));
         let portal = self.spawn();
         self.layout.portals.insert(portal);
         self.layout.kinds.insert(portal, kind.clone());
-        let oa = self.create_object(kind.clone(), sa);
-        let ob = self.create_object(kind, sb);
-        self.layout.portal_objects.insert(oa, PortalTarget { portal, target_object: ob });
-        self.layout.portal_objects.insert(ob, PortalTarget { portal, target_object: oa });
-        self.recalculate();
+        let oa = self.insert_object(kind.clone(), sa);
+        let ob = self.insert_object(kind, sb);
+        self.layout.portal_objects.insert(oa, PortalTarget {
+            portal,
+            target_object: ob,
+            entrance: true,
+        });
+        self.layout.portal_objects.insert(ob, PortalTarget {
+            portal,
+            target_object: oa,
+            entrance: two_way,
+        });
+        self.recalculate_component(sa);
         portal
     }
 
+    pub fn open_portal(&mut self, portal: Entity) {
+        assert!(self.is_portal(portal));
+        if self.layout.closed_portals.remove(&portal) {
+            self.recalculate_portal_or_space(portal);
+        }
+    }
+
+    /// Close a portal, preventing it from being traversed in either direction.
+    pub fn close_portal(&mut self, portal: Entity) {
+        assert!(self.is_portal(portal));
+        if self.layout.closed_portals.insert(portal) {
+            self.recalculate_portal_or_space(portal);
+        }
+    }
+
+    pub fn is_portal_open(&self, portal: Entity) -> bool {
+        self.is_portal(portal) && !self.layout.closed_portals.contains(&portal)
+    }
+
+    /// Whether the portal can be entered through this object when it is open.
+    pub fn is_portal_entrance(&self, object: Entity) -> bool {
+        self.layout.portal_objects.get(&object).map_or(false, |target| target.entrance)
+    }
+
+    /// Whether an agent at this object can currently traverse its portal.
+    pub fn can_traverse(&self, object: Entity) -> bool {
+        self.layout.portal_objects.get(&object).map_or(false, |target| {
+            target.entrance && !self.layout.closed_portals.contains(&target.portal)
+        })
+    }
+
     pub fn is_portal(&self, entity: Entity) -> bool {
         self.layout.portals.contains(&entity)
     }
@@ -134,6 +516,28 @@ impl World {
         self.layout.portal_objects.contains_key(&entity)
     }
 
+    /// The objects belonging to a portal.
+    pub fn portal_objects(&self, portal: Entity) -> impl Iterator<Item = Entity> + '_ {
+        self.layout.portal_objects.iter().filter_map(move |(object, target)| {
+            if target.portal == portal {
+                Some(*object)
+            } else {
+                None
+            }
+        })
+    }
+
+    pub fn space_portal_objects(&self, space: Entity) -> impl Iterator<Item = Entity> + '_ {
+        self.child_objects(space).filter(|object| self.is_portal_object(*object))
+    }
+
+    /// Spaces directly reachable from a space through one of its traversable portals.
+    pub fn adjacent_spaces(&self, space: Entity) -> impl Iterator<Item = Entity> + '_ {
+        self.space_portal_objects(space)
+            .filter(|object| self.can_traverse(*object))
+            .filter_map(|object| self.object_space(self.object_portal_target(object)?))
+    }
+
     pub fn object_portal(&self, object: Entity) -> Option<Entity> {
         self.layout.portal_objects.get(&object).map(|target| target.portal)
     }
@@ -142,65 +546,384 @@ impl World {
         self.layout.portal_objects.get(&object).map(|target| target.target_object)
     }
 
-    fn recalculate(&mut self) {
-        self.recalculate_paths();
-        self.recalculate_space_distances();
+    /// Spaces connected to a space through any number of portals, including itself.
+    ///
+    /// Portal direction and state are ignored, so components only change with the layout.
+    fn connected_spaces(&self, space: Entity) -> EntitySet {
+        let mut spaces = EntitySet::from_iter([space]);
+        let mut queue = VecDeque::from([space]);
+        while let Some(space) = queue.pop_front() {
+            let linked = self.space_portal_objects(space).filter_map(|object| {
+                self.object_space(self.object_portal_target(object)?)
+            });
+            for adjacent in linked {
+                if spaces.insert(adjacent) {
+                    queue.push_back(adjacent);
+                }
+            }
+        }
+        spaces
     }
 
-    fn recalculate_space_distances(&mut self) {
-        self.layout.space_distances.clear();
-        for path in self.find_paths() {
-            let mut spaces = path.into_iter()
-                .map(|area| self.object_space(area).unwrap())
-                .collect::<Vec<_>>();
-            let key = Pair::new(*spaces.first().unwrap(), *spaces.last().unwrap());
-            spaces.sort();
-            spaces.dedup();
-            if spaces.len() < 2 {
-                continue;
-            }
-            self.layout.space_distances.entry(key)
-                .and_modify(|current| *current = (*current).min(spaces.len()))
-                .or_insert(spaces.len());
+    /// Recalculates paths and distances for the connected component containing `space`.
+    ///
+    /// Paths never leave a component, so data for other components stays valid.
+    fn recalculate_component(&mut self, space: Entity) {
+        if let Some(deferred) = &mut self.layout.deferred_spaces {
+            deferred.insert(space);
+            return;
+        }
+        let spaces = self.connected_spaces(space);
+        self.recalculate_spaces(&spaces);
+    }
+
+    /// Recalculates paths and distances originating in any of the given spaces.
+    ///
+    /// The spaces have to cover complete connected components.
+    fn recalculate_spaces(&mut self, spaces: &EntitySet) {
+        if let Some(deferred) = &mut self.layout.deferred_spaces {
+            deferred.extend(spaces);
+            return;
+        }
+        let areas = spaces.iter()
+            .flat_map(|space| self.child_objects(*space))
+            .collect::<EntitySet>();
+        self.layout.paths.retain(|(first, _), _| !areas.contains(first));
+        self.layout.space_distances.retain(|(source, _), _| !spaces.contains(source));
+        let paths = self.find_paths(areas);
+        self.recalculate_space_distances(spaces);
+        for path in paths {
+            self.layout.paths.entry((path.start(), path.end())).or_default().push(path);
         }
     }
 
-    fn recalculate_paths(&mut self) {
-        self.layout.paths.clear();
-        for path in self.find_paths() {
-            let first = *path.first().unwrap();
-            let last = *path.last().unwrap();
-            self.layout.paths.entry((first, last)).or_default().push(
-                path.iter().rev().copied().fold(
-                    Value::List(Arc::new([])),
-                    |prev, area| Value::List(Arc::new([Value::Ext(area), prev])),
-                ),
-            );
+    fn recalculate_space_distances(&mut self, spaces: &EntitySet) {
+        for &source in spaces {
+            let mut settled = FnvHashMap::<Entity, f64>::default();
+            let mut queue = BinaryHeap::from([Reverse((FloatOrd(0.0), source))]);
+            while let Some(Reverse((FloatOrd(cost), space))) = queue.pop() {
+                if settled.contains_key(&space) {
+                    continue;
+                }
+                settled.insert(space, cost);
+                let local_cost = self.travel_cost(space);
+                for object in self.space_portal_objects(space) {
+                    if !self.can_traverse(object) {
+                        continue;
+                    }
+                    let portal = self.object_portal(object).unwrap();
+                    let target = self.object_portal_target(object).unwrap();
+                    let target_space = self.object_space(target).unwrap();
+                    if !settled.contains_key(&target_space) {
+                        let cost = cost + local_cost + self.travel_cost(portal);
+                        queue.push(Reverse((FloatOrd(cost), target_space)));
+                    }
+                }
+            }
+            settled.remove(&source);
+            for (space, cost) in settled {
+                self.layout.space_distances.insert((source, space), FloatOrd(cost));
+            }
         }
     }
 
-    fn find_paths(&self) -> Vec<Vec<Entity>> {
-        let mut buffer = self.areas().map(|area| Vec::from([area])).collect::<VecDeque<_>>();
+    /// Enumerates simple paths from the origins, cheapest first.
+    ///
+    /// Equally expensive paths are ordered by discovery, which keeps the result
+    /// deterministic.
+    fn find_paths<I>(&self, origins: I) -> Vec<Route>
+    where
+        I: IntoIterator<Item = Entity>,
+    {
+        let PathLimits { max_length, max_per_pair } = self.layout.path_limits;
+        let max_per_pair = max_per_pair.map_or(usize::MAX, NonZeroUsize::get);
+        let mut queue = origins.into_iter().enumerate().map(|(order, area)| PendingPath {
+            route: Route {
+                areas: Vec::from([area]),
+                portals: Vec::new(),
+                spaces: Vec::from([self.object_space(area).unwrap()]),
+                cost: 0.0,
+            },
+            order,
+        }).collect::<BinaryHeap<_>>();
+        let mut order = queue.len();
+        let mut pair_counts = FnvHashMap::<(Entity, Entity), usize>::default();
         let mut paths = Vec::new();
 
-        while let Some(path) = buffer.pop_front() {
-            if path.len() > 1 {
+        while let Some(PendingPath { route: path, .. }) = queue.pop() {
+            let first = path.start();
+            let last = path.end();
+            if path.areas.len() > 1 {
+                let count = pair_counts.entry((first, last)).or_default();
+                if *count >= max_per_pair {
+                    continue;
+                }
+                *count += 1;
                 paths.push(path.clone());
             }
-            let last = *path.last().unwrap();
-            let mut try_extend = |area| if !path.contains(&area) {
-                let mut path = path.clone();
-                path.push(area);
-                buffer.push_back(path);
-            };
-            if let Some(target) = self.object_portal_target(last) {
-                try_extend(target);
+            if max_length.map_or(false, |max| path.areas.len() >= max) {
+                continue;
             }
-            for local in self.child_objects(self.object_space(last).unwrap()) {
-                try_extend(local);
+            for (area, portal, step_cost) in self.route_steps(last) {
+                let saturated = pair_counts.get(&(first, area))
+                    .map_or(false, |count| *count >= max_per_pair);
+                if saturated || path.areas.contains(&area) {
+                    continue;
+                }
+                let mut next = path.clone();
+                next.areas.push(area);
+                next.portals.extend(portal);
+                let space = self.object_space(area).unwrap();
+                if next.spaces.last() != Some(&space) {
+                    next.spaces.push(space);
+                }
+                next.cost += step_cost;
+                queue.push(PendingPath { route: next, order });
+                order += 1;
             }
         }
 
         paths
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::behavior::{Effect, Value};
+    use crate::testing::symbol;
+    use crate::world::World;
+    use crate::world::entities::Entity;
+
+
+    /// Compares the incrementally maintained layout data with a full rebuild.
+    fn assert_matches_rebuild(world: &World) {
+        let mut rebuilt = world.clone();
+        rebuilt.layout.paths.clear();
+        rebuilt.layout.space_distances.clear();
+        let spaces = rebuilt.layout.spaces.clone();
+        rebuilt.recalculate_spaces(&spaces);
+        assert_eq!(world.layout.paths, rebuilt.layout.paths);
+        assert_eq!(world.layout.space_distances, rebuilt.layout.space_distances);
+    }
+
+    fn space(world: &mut World) -> Entity {
+        let space = world.create_space(symbol("room"));
+        assert_matches_rebuild(world);
+        space
+    }
+
+    fn object(world: &mut World, parent: Entity) -> Entity {
+        let object = world.create_object(symbol("floor"), parent);
+        assert_matches_rebuild(world);
+        object
+    }
+
+    fn portal(world: &mut World, spaces: (Entity, Entity)) -> Entity {
+        let portal = world.create_portal(symbol("door"), spaces);
+        assert_matches_rebuild(world);
+        portal
+    }
+
+    #[test]
+    fn areas_before_portals() {
+        let mut world = World::default();
+        let a = space(&mut world);
+        let b = space(&mut world);
+        let c = space(&mut world);
+        for parent in [a, a, b, c, c] {
+            object(&mut world, parent);
+        }
+        portal(&mut world, (a, b));
+        portal(&mut world, (b, c));
+        portal(&mut world, (c, a));
+        assert!(world.spaces_by_distance(a).eq([b, c]) || world.spaces_by_distance(a).eq([c, b]));
+    }
+
+    #[test]
+    fn portals_before_areas() {
+        let mut world = World::default();
+        let a = space(&mut world);
+        let b = space(&mut world);
+        let c = space(&mut world);
+        portal(&mut world, (c, b));
+        portal(&mut world, (a, b));
+        for parent in [c, b, a, a, b] {
+            let area = object(&mut world, parent);
+            object(&mut world, area);
+        }
+        assert_eq!(world.spaces_by_distance(a).collect::<Vec<_>>(), [b, c]);
+    }
+
+    #[test]
+    fn portal_merging_components() {
+        let mut world = World::default();
+        let a = space(&mut world);
+        let b = space(&mut world);
+        let c = space(&mut world);
+        let d = space(&mut world);
+        for parent in [a, b, c, d] {
+            object(&mut world, parent);
+        }
+        portal(&mut world, (a, b));
+        portal(&mut world, (c, d));
+        assert_eq!(world.spaces_by_distance(a).collect::<Vec<_>>(), [b]);
+        portal(&mut world, (b, c));
+        assert_eq!(world.spaces_by_distance(a).collect::<Vec<_>>(), [b, c, d]);
+        portal(&mut world, (d, a));
+        assert_eq!(world.spaces_by_distance(a).count(), 3);
+    }
+
+    #[test]
+    fn layout_changes() {
+        let mut world = World::default();
+        let a = space(&mut world);
+        let b = space(&mut world);
+        let c = space(&mut world);
+        let area = object(&mut world, a);
+        let item = object(&mut world, area);
+        object(&mut world, b);
+        object(&mut world, c);
+        let door = portal(&mut world, (a, b));
+        let chute = world.create_one_way_portal(symbol("chute"), (b, c));
+        assert_matches_rebuild(&world);
+        let gate = portal(&mut world, (c, a));
+
+        world.set_travel_cost(door, 5.0);
+        assert_matches_rebuild(&world);
+        world.set_kind_travel_cost(symbol("room"), 2.0);
+        assert_matches_rebuild(&world);
+        world.close_portal(gate);
+        assert_matches_rebuild(&world);
+        world.move_object(item, c).unwrap();
+        assert_matches_rebuild(&world);
+        world.move_object(item, area).unwrap();
+        assert_matches_rebuild(&world);
+        world.remove_portal(chute);
+        assert_matches_rebuild(&world);
+        world.open_portal(gate);
+        assert_matches_rebuild(&world);
+        world.remove_object(area);
+        assert_matches_rebuild(&world);
+        world.remove_space(b);
+        assert_matches_rebuild(&world);
+    }
+
+    #[test]
+    fn deferred_layout_updates() {
+        let mut world = World::default();
+        let (a, b, c) = world.defer_layout_updates(|world| {
+            let a = world.create_space(symbol("room"));
+            let b = world.create_space(symbol("room"));
+            let c = world.create_space(symbol("room"));
+            for parent in [a, a, b, c] {
+                world.create_object(symbol("floor"), parent);
+            }
+            world.create_portal(symbol("door"), (a, b));
+            world.create_portal(symbol("door"), (b, c));
+            assert!(world.spaces_by_distance(a).next().is_none());
+            (a, b, c)
+        });
+        assert_matches_rebuild(&world);
+        assert_eq!(world.spaces_by_distance(a).collect::<Vec<_>>(), [b, c]);
+
+        world.defer_layout_updates(|world| {
+            world.remove_space(b);
+            world.create_portal(symbol("door"), (c, a));
+        });
+        assert_matches_rebuild(&world);
+        assert_eq!(world.spaces_by_distance(a).collect::<Vec<_>>(), [c]);
+    }
+
+    fn portal_object(world: &World, portal: Entity, space: Entity) -> Entity {
+        world.portal_objects(portal)
+            .find(|object| world.object_space(*object) == Some(space))
+            .unwrap()
+    }
+
+    #[test]
+    fn removals_cascade() {
+        let mut world = World::default();
+        let hall = world.create_space(symbol("room"));
+        let yard = world.create_space(symbol("room"));
+        let cellar = world.create_space(symbol("room"));
+        let hearth = world.create_object(symbol("floor"), hall);
+        let chest = world.create_object(symbol("chest"), hearth);
+        let coin = world.create_object(symbol("coin"), chest);
+        let lawn = world.create_object(symbol("floor"), yard);
+        let door = world.create_portal(symbol("door"), (hall, yard));
+        let hatch = world.create_portal(symbol("hatch"), (yard, cellar));
+        let door_yard = portal_object(&world, door, yard);
+        let mat = world.create_object(symbol("mat"), door_yard);
+        let hatch_yard = portal_object(&world, hatch, yard);
+        let hatch_cellar = portal_object(&world, hatch, cellar);
+        let resident = world.create_agent_with_kind(symbol("walker"), hearth);
+        let visitor = world.create_agent(door_yard);
+        let climber = world.create_agent(hatch_cellar);
+        let observer = world.create_agent(lawn);
+        world.apply_effects(resident, &[
+            Effect::Remember { key: symbol("home"), value: Value::Ext(hearth) },
+            Effect::StartActivity { name: symbol("resting"), duration: Value::Int(5) },
+            Effect::SetAgentTag { entity: lawn, tag: symbol("seen") },
+        ]).unwrap();
+        world.set_agent_tag(observer, chest, symbol("seen")).unwrap();
+        world.set_agent_attribute_value(observer, coin, symbol("worth"), Value::Int(3)).unwrap();
+        world.set_agent_tag(observer, mat, symbol("seen")).unwrap();
+
+        world.remove_object(hearth);
+        for entity in [hearth, chest, coin, resident] {
+            assert!(!world.contains(entity));
+        }
+        assert!(!world.is_agent(resident));
+        assert_eq!(world.agent_memory(resident, &symbol("home")), None);
+        assert!(world.agent_activity(resident).is_none());
+        assert_eq!(world.layout_kind(resident), None);
+        assert_eq!(world.has_agent_tag(resident, lawn, &symbol("seen")), Ok(false));
+        assert_eq!(world.agent_tagged(observer, &symbol("seen")).collect::<Vec<_>>(), [mat]);
+        assert!(world.agent_attribute_value(observer, coin, &symbol("worth")).is_err());
+
+        world.remove_space(hall);
+        for entity in [hall, door, door_yard, mat, visitor] {
+            assert!(!world.contains(entity));
+        }
+        assert!(!world.is_agent(visitor));
+        assert!(world.agent_tagged(observer, &symbol("seen")).next().is_none());
+        assert!(world.space_portal_objects(yard).eq([hatch_yard]));
+
+        world.remove_portal(hatch);
+        for entity in [hatch, hatch_yard, hatch_cellar, climber] {
+            assert!(!world.contains(entity));
+        }
+        assert!(world.agents().eq([observer]));
+        assert!(world.child_objects(yard).eq([lawn]));
+        assert!(world.child_objects(cellar).next().is_none());
+        assert_matches_rebuild(&world);
+    }
+
+    #[test]
+    fn one_way_and_closed_portals_are_directional() {
+        let mut world = World::default();
+        let hall = world.create_space(symbol("room"));
+        let yard = world.create_space(symbol("room"));
+        let hearth = world.create_object(symbol("floor"), hall);
+        let lawn = world.create_object(symbol("floor"), yard);
+        let chute = world.create_one_way_portal(symbol("chute"), (hall, yard));
+        assert!(world.spaces_by_distance(hall).eq([yard]));
+        assert!(world.spaces_by_distance(yard).next().is_none());
+        assert_eq!(world.route(hearth, lawn).unwrap().portals(), [chute]);
+        assert!(world.route(lawn, hearth).is_none());
+
+        let gate = world.create_portal(symbol("gate"), (hall, yard));
+        assert!(world.spaces_by_distance(yard).eq([hall]));
+        assert_eq!(world.route(lawn, hearth).unwrap().portals(), [gate]);
+
+        world.close_portal(gate);
+        assert!(world.spaces_by_distance(yard).next().is_none());
+        assert!(world.route(lawn, hearth).is_none());
+        assert_eq!(world.route(hearth, lawn).unwrap().portals(), [chute]);
+
+        world.close_portal(chute);
+        assert!(world.spaces_by_distance(hall).next().is_none());
+        assert!(world.route(hearth, lawn).is_none());
+        assert_matches_rebuild(&world);
+    }
+}
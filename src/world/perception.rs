@@ -0,0 +1,84 @@
+use std::collections::VecDeque;
+
+use super::World;
+use super::entities::{Entity, EntitySet};
+
+
+#[derive(Debug, Clone, Default)]
+pub(super) struct WorldPerception {
+    range: PerceptionRange,
+}
+
+/// How far agents can perceive other entities.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum PerceptionRange {
+    /// Agents perceive the whole world.
+    #[default]
+    Unlimited,
+    /// Agents perceive their own area and everything in it.
+    Area,
+    /// Agents perceive their own space and everything in it.
+    Space,
+    /// Agents perceive all spaces reachable by crossing at most the given number of portals.
+    Portals(usize),
+}
+
+impl World {
+    pub fn set_perception_range(&mut self, range: PerceptionRange) {
+        self.perception.range = range;
+    }
+
+    pub fn perception_range(&self) -> PerceptionRange {
+        self.perception.range
+    }
+
+    /// The set of entities the agent can perceive, or `None` if perception is unlimited.
+    pub fn perceivable(&self, agent: Entity) -> Option<EntitySet> {
+        let location = self.agent_location(agent).expect("valid agent");
+        let space = self.object_space(location).expect("agent location space");
+        let spaces = match self.perception.range {
+            PerceptionRange::Unlimited => return None,
+            PerceptionRange::Area => {
+                let area = self.object_area(location).expect("agent location area");
+                let mut perceived = EntitySet::from_iter([space, area]);
+                self.collect_descendants(area, &mut perceived);
+                perceived.extend(self.agents().filter(|other| {
+                    self.agent_location(*other)
+                        .and_then(|location| self.object_area(location)) == Some(area)
+                }));
+                return Some(perceived);
+            },
+            PerceptionRange::Space => EntitySet::from_iter([space]),
+            PerceptionRange::Portals(range) => self.spaces_within_portals(space, range),
+        };
+        let mut perceived = spaces.clone();
+        for space in &spaces {
+            self.collect_descendants(*space, &mut perceived);
+            for portal_object in self.space_portal_objects(*space) {
+                perceived.extend(self.object_portal(portal_object));
+            }
+        }
+        perceived.extend(self.agents().filter(|other| {
+            self.agent_location(*other)
+                .and_then(|location| self.object_space(location))
+                .map_or(false, |space| spaces.contains(&space))
+        }));
+        Some(perceived)
+    }
+
+    fn spaces_within_portals(&self, origin: Entity, range: usize) -> EntitySet {
+        let mut spaces = EntitySet::from_iter([origin]);
+        let mut queue = VecDeque::from([(origin, 0)]);
+        while let Some((space, distance)) = queue.pop_front() {
+            if distance >= range {
+                continue;
+            }
+            for adjacent in self.adjacent_spaces(space) {
+                if spaces.insert(adjacent) {
+                    queue.push_back((adjacent, distance + 1));
+                }
+            }
+        }
+        spaces
+    }
+}
\ No newline at end of file
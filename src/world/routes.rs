@@ -0,0 +1,86 @@
+use super::World;
+use super::entities::Entity;
+
+
+/// A route between two areas.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Route {
+    pub(super) areas: Vec<Entity>,
+    pub(super) portals: Vec<Entity>,
+    pub(super) spaces: Vec<Entity>,
+    pub(super) cost: f64,
+}
+
+impl Route {
+    /// All areas along the route in order, including the start and end.
+    pub fn areas(&self) -> &[Entity] {
+        &self.areas
+    }
+
+    /// The portals crossed along the route in order.
+    pub fn portals(&self) -> &[Entity] {
+        &self.portals
+    }
+
+    /// The spaces visited along the route in order.
+    pub fn spaces(&self) -> &[Entity] {
+        &self.spaces
+    }
+
+    /// The total travel cost of the route.
+    pub fn cost(&self) -> f64 {
+        self.cost
+    }
+
+    pub fn start(&self) -> Entity {
+        *self.areas.first().unwrap()
+    }
+
+    pub fn end(&self) -> Entity {
+        *self.areas.last().unwrap()
+    }
+
+    /// The area to move to next, if the route isn't already complete.
+    pub fn next_area(&self) -> Option<Entity> {
+        self.areas.get(1).copied()
+    }
+}
+
+impl World {
+    /// The cheapest precalculated route between two areas.
+    ///
+    /// Routes longer than the world's
+    /// [`PathLimits::max_length`](super::layout::PathLimits::max_length) are not
+    /// precalculated.
+    pub fn route(&self, from: Entity, to: Entity) -> Option<Route> {
+        if !self.is_area(from) || !self.is_area(to) {
+            return None;
+        }
+        if from == to {
+            return Some(Route {
+                areas: Vec::from([from]),
+                portals: Vec::new(),
+                spaces: Vec::from([self.object_space(from).unwrap()]),
+                cost: 0.0,
+            });
+        }
+        self.routes(from, to).first().cloned()
+    }
+
+    /// Areas reachable from an area in a single step, with the crossed portal and the cost.
+    pub(super) fn route_steps(
+        &self,
+        area: Entity,
+    ) -> impl Iterator<Item = (Entity, Option<Entity>, f64)> + '_ {
+        let space = self.object_space(area).unwrap();
+        let local_cost = self.travel_cost(space);
+        let local = self.space_areas(space)
+            .filter(move |local| *local != area)
+            .map(move |local| (local, None, local_cost));
+        let portal = self.object_portal(area).filter(|_| self.can_traverse(area)).map(|portal| {
+            let target = self.object_portal_target(area).unwrap();
+            (target, Some(portal), self.travel_cost(portal))
+        });
+        local.chain(portal)
+    }
+}
@@ -0,0 +1,198 @@
+use std::collections::BTreeMap;
+
+use fnv::FnvHashMap;
+
+use crate::behavior::{Behavior, Effect, Evaluation};
+
+use super::World;
+use super::conflicts::{ConflictResolver, PriorityResolver};
+use super::effects::EffectError;
+use super::entities::Entity;
+
+
+#[derive(Debug, Clone)]
+pub(super) struct WorldStep {
+    tick: u64,
+    time: f64,
+    time_step: f64,
+    conflict_seed: u64,
+    rejections: FnvHashMap<Entity, Vec<RejectedEffect>>,
+}
+
+impl Default for WorldStep {
+    fn default() -> Self {
+        Self {
+            tick: 0,
+            time: 0.0,
+            time_step: 1.0,
+            conflict_seed: 0,
+            rejections: FnvHashMap::default(),
+        }
+    }
+}
+
+/// Effects decided by each agent during the decision phase of a step.
+#[derive(Debug, Clone, Default)]
+pub struct Decisions {
+    effects: BTreeMap<Entity, Vec<Effect>>,
+}
+
+impl Decisions {
+    pub fn insert(&mut self, agent: Entity, effects: Vec<Effect>) {
+        self.effects.insert(agent, effects);
+    }
+
+    pub fn agent_effects(&self, agent: Entity) -> &[Effect] {
+        self.effects.get(&agent).map_or(&[], |effects| effects.as_slice())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Entity, &[Effect])> + '_ {
+        self.effects.iter().map(|(agent, effects)| (*agent, effects.as_slice()))
+    }
+}
+
+impl FromIterator<(Entity, Vec<Effect>)> for Decisions {
+    fn from_iter<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = (Entity, Vec<Effect>)>,
+    {
+        Self { effects: iter.into_iter().collect() }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct StepReport {
+    pub rejected: Vec<RejectedEffect>,
+}
+
+/// Effect that caused all effects of an agent's decision to be rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RejectedEffect {
+    pub agent: Entity,
+    pub effect: Effect,
+    pub rejection: Rejection,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    /// Another agent won the claim on the entity.
+    Conflict { entity: Entity, winner: Entity },
+    /// The effect could not be applied.
+    Failed(EffectError),
+}
+
+impl World {
+    /// Runs a full step, deciding for all agents and then applying their effects.
+    pub fn step(&mut self, behavior: &Behavior) -> StepReport {
+        self.step_with(behavior, Evaluation::Serial)
+    }
+
+    /// Runs a full step using the given evaluation mode for the decision phase.
+    pub fn step_with(&mut self, behavior: &Behavior, evaluation: Evaluation) -> StepReport {
+        let decisions = behavior.decide_with(self, evaluation);
+        self.apply_decisions(decisions)
+    }
+
+    /// Applies the effects of a decision phase in agent order.
+    ///
+    /// All decisions are expected to have been made against the same world state, so no
+    /// agent observes the effects of another agent during the same step. The effects of
+    /// each agent are applied as a single transaction.
+    ///
+    /// Conflicts are resolved with a [`PriorityResolver`] using the world's conflict seed.
+    pub fn apply_decisions(&mut self, decisions: Decisions) -> StepReport {
+        let mut resolver = PriorityResolver::new(self.step.conflict_seed);
+        self.apply_decisions_with(decisions, &mut resolver)
+    }
+
+    /// Applies the effects of a decision phase using a custom conflict resolver.
+    ///
+    /// Agents losing a conflict have all of their effects rejected.
+    pub fn apply_decisions_with<R>(
+        &mut self,
+        decisions: Decisions,
+        resolver: &mut R,
+    ) -> StepReport
+    where
+        R: ConflictResolver + ?Sized,
+    {
+        let mut report = StepReport::default();
+        let lost_claims = self.resolve_conflicts(&decisions, resolver);
+        let mut effects = decisions.effects;
+        for lost in lost_claims {
+            let Some(agent_effects) = effects.remove(&lost.agent) else {
+                continue;
+            };
+            let effect = agent_effects.into_iter()
+                .find(|effect| resolver.claim(self, lost.agent, effect) == Some(lost.entity))
+                .expect("claiming effect");
+            report.rejected.push(RejectedEffect {
+                agent: lost.agent,
+                effect,
+                rejection: Rejection::Conflict { entity: lost.entity, winner: lost.winner },
+            });
+        }
+        for (agent, mut agent_effects) in effects {
+            if let Err(failure) = self.apply_effects(agent, &agent_effects) {
+                report.rejected.push(RejectedEffect {
+                    agent,
+                    effect: agent_effects.swap_remove(failure.index),
+                    rejection: Rejection::Failed(failure.error),
+                });
+            }
+        }
+        self.step.rejections.clear();
+        for rejected in &report.rejected {
+            self.step.rejections.entry(rejected.agent).or_default().push(rejected.clone());
+        }
+        self.step.tick += 1;
+        self.step.time += self.step.time_step;
+        self.complete_activities();
+        report
+    }
+
+    /// Number of steps applied to the world so far.
+    pub fn tick(&self) -> u64 {
+        self.step.tick
+    }
+
+    /// World time, advanced by the time step with each applied step.
+    pub fn time(&self) -> f64 {
+        self.step.time
+    }
+
+    pub fn time_step(&self) -> f64 {
+        self.step.time_step
+    }
+
+    pub fn set_time_step(&mut self, time_step: f64) {
+        assert!(time_step >= 0.0);
+        self.step.time_step = time_step;
+    }
+
+    /// Seed used to break ties between conflicting agents in [`World::apply_decisions`].
+    pub fn conflict_seed(&self) -> u64 {
+        self.step.conflict_seed
+    }
+
+    pub fn set_conflict_seed(&mut self, seed: u64) {
+        self.step.conflict_seed = seed;
+    }
+
+    /// Effects of the agent that were rejected during the last applied step.
+    pub fn agent_rejections(&self, agent: Entity) -> &[RejectedEffect] {
+        self.step.rejections.get(&agent).map_or(&[], |rejections| rejections.as_slice())
+    }
+
+    pub(super) fn remove_agent_rejections(&mut self, agent: Entity) {
+        self.step.rejections.remove(&agent);
+    }
+
+    /// Entities the agent lost to other agents during the last applied step.
+    pub fn lost_claims(&self, agent: Entity) -> impl Iterator<Item = Entity> + '_ {
+        self.agent_rejections(agent).iter().filter_map(|rejected| match rejected.rejection {
+            Rejection::Conflict { entity, .. } => Some(entity),
+            Rejection::Failed(_) => None,
+        })
+    }
+}
\ No newline at end of file